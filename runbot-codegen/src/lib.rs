@@ -28,16 +28,168 @@ macro_rules! emit {
 #[derive(Default, Debug)]
 struct ProcessorAttributes {
     command: Option<syn::LitStr>,
+    filters: ProcessorFilters,
 }
 
 impl ProcessorAttributes {
     fn parse(&mut self, meta: syn::meta::ParseNestedMeta) -> syn::Result<()> {
         if meta.path.is_ident("command") {
             self.command = Some(meta.value()?.parse()?);
-            Ok(())
+        } else if meta.path.is_ident("group_only") {
+            self.filters.group_only = true;
+        } else if meta.path.is_ident("private_only") {
+            self.filters.private_only = true;
+        } else if meta.path.is_ident("at_me") {
+            self.filters.at_me = true;
+        } else if meta.path.is_ident("groups") {
+            self.filters.groups = Some(meta.value()?.parse()?);
+        } else if meta.path.is_ident("users") {
+            self.filters.users = Some(meta.value()?.parse()?);
+        } else if meta.path.is_ident("sub_type") {
+            self.filters.sub_type = Some(meta.value()?.parse()?);
         } else {
-            Ok(())
+            return Err(meta.error(
+                "unsupported processor attribute, expected one of: command, group_only, private_only, at_me, groups, users, sub_type",
+            ));
+        }
+        if self.filters.group_only && self.filters.private_only {
+            return Err(meta.error("group_only and private_only can not be used together"));
+        }
+        Ok(())
+    }
+}
+
+/// 事件过滤, 不满足时处理器直接返回 `Ok(false)`
+#[derive(Default, Debug)]
+struct ProcessorFilters {
+    group_only: bool,
+    private_only: bool,
+    groups: Option<syn::ExprArray>,
+    users: Option<syn::ExprArray>,
+    at_me: bool,
+    sub_type: Option<syn::LitStr>,
+}
+
+impl ProcessorFilters {
+    fn is_empty(&self) -> bool {
+        !self.group_only
+            && !self.private_only
+            && self.groups.is_none()
+            && self.users.is_none()
+            && !self.at_me
+            && self.sub_type.is_none()
+    }
+
+    /// 生成过滤代码, processor_type 为 Message / Notice / Request / Post
+    fn guard(
+        &self,
+        processor_type: &str,
+        event: &proc_macro2::Ident,
+        span: proc_macro2::Span,
+    ) -> proc_macro2::TokenStream {
+        let mut guard = quote! {};
+        if self.is_empty() {
+            return guard;
+        }
+        let groups = self.groups.as_ref().map(|groups| {
+            let elems = groups.elems.iter();
+            quote! {[#(#elems),*]}
+        });
+        let users = self.users.as_ref().map(|users| {
+            let elems = users.elems.iter();
+            quote! {[#(#elems),*]}
+        });
+        match processor_type {
+            "Message" => {
+                if self.group_only || groups.is_some() {
+                    guard.extend(quote! {
+                        if !matches!(#event.message_type, ::runbot::event::MessageType::Group) {
+                            return Ok(false);
+                        }
+                    });
+                }
+                if self.private_only {
+                    guard.extend(quote! {
+                        if !matches!(#event.message_type, ::runbot::event::MessageType::Private) {
+                            return Ok(false);
+                        }
+                    });
+                }
+                if let Some(groups) = groups {
+                    guard.extend(quote! {
+                        if !<[i64]>::contains(&#groups, &#event.group_id) {
+                            return Ok(false);
+                        }
+                    });
+                }
+                if let Some(users) = users {
+                    guard.extend(quote! {
+                        if !<[i64]>::contains(&#users, &#event.user_id) {
+                            return Ok(false);
+                        }
+                    });
+                }
+                if self.at_me {
+                    guard.extend(quote! {
+                        if !#event.is_at_me() {
+                            return Ok(false);
+                        }
+                    });
+                }
+                if let Some(sub_type) = &self.sub_type {
+                    let pattern = match sub_type.value().as_str() {
+                        "friend" => quote! {::runbot::event::MessageSubType::Friend},
+                        "normal" => quote! {::runbot::event::MessageSubType::Normal},
+                        _ => quote! {
+                            ::runbot::event::MessageSubType::Unknown(sub_type) if sub_type.as_str() == #sub_type
+                        },
+                    };
+                    guard.extend(quote! {
+                        if !matches!(&#event.sub_type, #pattern) {
+                            return Ok(false);
+                        }
+                    });
+                }
+            }
+            "Notice" | "Request" => {
+                if self.private_only || self.at_me {
+                    abort!(
+                        &span,
+                        "private_only and at_me are only supported for &Message"
+                    );
+                }
+                if self.group_only {
+                    guard.extend(quote! {
+                        if #event.group_id().is_none() {
+                            return Ok(false);
+                        }
+                    });
+                }
+                if let Some(groups) = groups {
+                    guard.extend(quote! {
+                        if !#event.group_id().is_some_and(|id| <[i64]>::contains(&#groups, &id)) {
+                            return Ok(false);
+                        }
+                    });
+                }
+                if let Some(users) = users {
+                    guard.extend(quote! {
+                        if !#event.user_id().is_some_and(|id| <[i64]>::contains(&#users, &id)) {
+                            return Ok(false);
+                        }
+                    });
+                }
+                if let Some(sub_type) = &self.sub_type {
+                    guard.extend(quote! {
+                        if #event.sub_type() != Some(#sub_type) {
+                            return Ok(false);
+                        }
+                    });
+                }
+            }
+            _ => abort!(&span, "filters are not supported for &Post"),
         }
+        guard
     }
 }
 
@@ -48,13 +200,13 @@ pub fn processor(args: TokenStream, input: TokenStream) -> TokenStream {
     let command_parser = syn::meta::parser(|meta| attrs.parse(meta));
     parse_macro_input!(args with command_parser);
     if attrs.command.is_some() {
-        command_processor(attrs.command.unwrap(), input)
+        command_processor(attrs.command.unwrap(), attrs.filters, input)
     } else {
-        normal_processor(input)
+        normal_processor(attrs.filters, input)
     }
 }
 
-fn normal_processor(input: TokenStream) -> TokenStream {
+fn normal_processor(filters: ProcessorFilters, input: TokenStream) -> TokenStream {
     let method = parse_macro_input!(input as syn::ItemFn);
     let method_clone = method.clone();
     if method.sig.asyncness.is_none() {
@@ -145,6 +297,11 @@ fn normal_processor(input: TokenStream) -> TokenStream {
         },
         _ => abort!(&second_param.span(), "second parameter must be a parameter"),
     };
+    let filter_guard = filters.guard(
+        &processor_type.to_string(),
+        &second_param_ident,
+        method.sig.span(),
+    );
     emit!(quote::quote! {
         #[derive(Copy, Clone, Default, Debug)]
         #vis struct #struct_name;
@@ -162,6 +319,7 @@ fn normal_processor(input: TokenStream) -> TokenStream {
             }
 
             #asyncness fn #trait_fn_name(&self, #first_param, #second_param) #return_type {
+                #filter_guard
                 #fn_name(#first_param_ident, #second_param_ident).await
             }
         }
@@ -335,7 +493,11 @@ pub fn unknown_enum_serde_and_parse(input: TokenStream) -> TokenStream {
     emit!(r#gen)
 }
 
-fn command_processor(bot_command_pattern_str: syn::LitStr, input: TokenStream) -> TokenStream {
+fn command_processor(
+    bot_command_pattern_str: syn::LitStr,
+    filters: ProcessorFilters,
+    input: TokenStream,
+) -> TokenStream {
     // method
     let method = parse_macro_input!(input as syn::ItemFn);
     let span = method.span();
@@ -533,6 +695,7 @@ fn command_processor(bot_command_pattern_str: syn::LitStr, input: TokenStream) -
         },
         _ => abort!(&second_param.span(), "second parameter must be a parameter"),
     };
+    let filter_guard = filters.guard("Message", &second_param_ident, method.sig.span());
 
     let mut command_item_ident_stream = quote! {};
     for item in paramed_bot_command_items {
@@ -956,6 +1119,7 @@ fn command_processor(bot_command_pattern_str: syn::LitStr, input: TokenStream) -
                 )
             }
             #asyncness fn process_message(&self, #first_param, #second_param) #return_type {
+                #filter_guard
                 #define_command_lopper
                 #define_lopper_value
                 #fn_name(#first_param_ident, #second_param_ident #command_item_ident_stream).await
@@ -1501,12 +1665,10 @@ pub fn module(args: TokenStream, input: TokenStream) -> TokenStream {
     let into_process = quote! {
         impl Into<Processor> for #struct_ident {
             fn into(self) -> Processor {
-                Processor::Module(Box::new(ProcessModule {
-                    id: Self::id(),
-                    name: Self::name(),
-                    help: Self::help(),
-                    processors: Self::processors().into(),
-                }))
+                Processor::Module(Box::new(
+                    ProcessModule::new(Self::id(), Self::name(), Self::help(), Self::processors())
+                        .middlewares(Self::middlewares()),
+                ))
             }
         }
     };
@@ -1539,6 +1701,51 @@ pub fn module(args: TokenStream, input: TokenStream) -> TokenStream {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_processor_filters() {
+        use syn::parse::Parser;
+
+        let mut attrs = ProcessorAttributes::default();
+        syn::meta::parser(|meta| attrs.parse(meta))
+            .parse_str(r#"group_only, groups = [1, 2], at_me, sub_type = "normal""#)
+            .unwrap();
+        assert!(attrs.command.is_none());
+        assert!(attrs.filters.group_only && attrs.filters.at_me);
+        let event = proc_macro2::Ident::new("message", proc_macro2::Span::call_site());
+        let guard = attrs
+            .filters
+            .guard("Message", &event, proc_macro2::Span::call_site())
+            .to_string();
+        assert!(guard.contains("MessageSubType :: Normal"));
+        assert!(!guard.contains("serde_json"));
+        assert_eq!(attrs.filters.groups.unwrap().elems.len(), 2);
+        assert_eq!(attrs.filters.sub_type.unwrap().value(), "normal");
+
+        let mut attrs = ProcessorAttributes::default();
+        syn::meta::parser(|meta| attrs.parse(meta))
+            .parse_str(r#"users = [1], sub_type = "poke""#)
+            .unwrap();
+        let event = proc_macro2::Ident::new("notice", proc_macro2::Span::call_site());
+        let guard = attrs
+            .filters
+            .guard("Notice", &event, proc_macro2::Span::call_site())
+            .to_string();
+        assert!(guard.contains("notice . sub_type () != Some (\"poke\")"));
+
+        let mut attrs = ProcessorAttributes::default();
+        assert!(
+            syn::meta::parser(|meta| attrs.parse(meta))
+                .parse_str("group_only, private_only")
+                .is_err()
+        );
+        let mut attrs = ProcessorAttributes::default();
+        assert!(
+            syn::meta::parser(|meta| attrs.parse(meta))
+                .parse_str("group_onyl")
+                .is_err()
+        );
+    }
+
     #[test]
     fn test_parse_template_repeat() {
         let template = "{:n}{time:n}?{:n}*{time:n}+";
@@ -290,6 +290,27 @@ mod tests {
         assert!(result);
     }
 
+    #[tokio::test]
+    async fn test_demo_command_ban_mock() {
+        use runbot::testing::MockOneBot;
+
+        let mock = MockOneBot::start().await.unwrap();
+        let bot_ctx = BotContextBuilder::new()
+            .url(mock.url())
+            .add_processor(DEMO_COMMAND_BAN)
+            .build()
+            .unwrap();
+        tokio::spawn(loop_client(bot_ctx.clone()));
+        mock.wait_connected().await;
+
+        mock.push_group_message(123, 456, "-ban 10m 1234567890");
+        mock.expect_action("send_group_msg")
+            .with_param("group_id", 123)
+            .with_text("禁用用户 [1234567890] 600秒")
+            .await;
+        bot_ctx.shutdown().await.unwrap();
+    }
+
     #[tokio::test]
     async fn test_shutdown() {
         use runbot::connection::loop_client;
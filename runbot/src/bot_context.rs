@@ -1,27 +1,76 @@
 use std::fmt::{self, Debug};
+use std::future::Future;
+use std::path::PathBuf;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicI64, AtomicU64, AtomicUsize, Ordering};
 use std::vec;
 
+use crate::bot_manager::BotManager;
+use crate::connection::ClientRole;
+use crate::dispatch::{DispatchPolicy, Dispatcher};
 use crate::error::{Error, Result};
+use crate::error_policy::ErrorPolicy;
 use crate::event::*;
+use crate::heartbeat::HeartbeatWatchdog;
+use crate::hooks::ConnectionHooks;
+use crate::metrics::Metrics;
+use crate::middleware::{Middleware, Middlewares};
+use crate::offline::{OfflineAction, OfflineBuffer, OfflineQueue};
+use crate::outbound::{Outbound, OutboundPolicy, QueuedAction};
 use crate::process::{Processor, loop_processors};
+use crate::proxy::Proxy;
+use crate::reconnect::{ReconnectAttempt, ReconnectObserver, ReconnectPolicy};
+use crate::tls::{ClientTlsConfig, ServerTlsConfig};
+use crate::traffic::{Direction, TrafficRecorder};
 use async_trait::async_trait;
+use bytes::Bytes;
 use dashmap::DashMap;
 use futures_util::SinkExt;
 use futures_util::stream::SplitSink;
+use http_body_util::{BodyExt, Full};
+use hyper_util::client::legacy::Client;
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::rt::TokioExecutor;
 use serde_json::json;
 use tokio::io::{AsyncRead, AsyncWrite};
-use tokio::sync::Mutex;
-use tokio::time::Duration;
+use tokio::sync::{Mutex, oneshot};
+use tokio::time::{Duration, Instant};
 use tokio_tungstenite::WebSocketStream;
+use tracing::Instrument;
+use tracing::field::Empty;
+
+pub(crate) type EchoNotifer = Arc<DashMap<String, tokio::sync::mpsc::Sender<Response>>>;
 
 // todo: default time for bot context
 #[derive(Debug)]
 pub struct BotContext {
-    pub(crate) connection: Mutex<Option<BotConnection>>,
+    pub(crate) connection: Arc<Mutex<Option<BotConnection>>>,
     pub url: Option<String>,
-    pub id: i64,
+    pub unix_socket: Option<PathBuf>,
+    pub proxy: Option<Proxy>,
+    pub http_api_url: Option<String>,
+    pub http_post_bind: Option<String>,
+    pub access_token: Option<String>,
+    pub secret: Option<String>,
+    pub reconnect_policy: ReconnectPolicy,
+    pub(crate) reconnect_observer: Option<ReconnectObserver>,
+    pub(crate) tls_config: Option<Arc<rustls::ClientConfig>>,
+    pub heartbeat_watchdog: Option<HeartbeatWatchdog>,
+    pub(crate) heartbeat: tokio::sync::watch::Sender<Option<Heartbeat>>,
+    pub(crate) outbound: Option<Arc<Outbound>>,
+    pub(crate) dispatcher: Option<Arc<Dispatcher>>,
+    pub(crate) offline: Option<Arc<OfflineQueue>>,
+    pub(crate) hooks: ConnectionHooks,
+    pub(crate) recorder: Option<Arc<TrafficRecorder>>,
+    pub(crate) metrics: Option<Arc<Metrics>>,
+    pub ping_interval: Option<Duration>,
+    pub pong_timeout: Duration,
+    pub(crate) id: Arc<AtomicI64>,
+    pub(crate) draining: AtomicBool,
+    pub(crate) in_flight: AtomicUsize,
     pub processors: Arc<Vec<Processor>>,
+    pub(crate) middlewares: Arc<Middlewares>,
+    pub(crate) error_policy: ErrorPolicy,
     pub echo_notifer: Arc<DashMap<String, tokio::sync::mpsc::Sender<Response>>>,
     pub(crate) shutdown_tx: Mutex<Option<tokio::sync::watch::Sender<bool>>>,
     pub(crate) shutdown_rx: Mutex<Option<tokio::sync::watch::Receiver<bool>>>,
@@ -32,8 +81,18 @@ pub struct EchoAsyncResponse(
     String,
     tokio::sync::mpsc::Receiver<Response>,
     Arc<DashMap<String, tokio::sync::mpsc::Sender<Response>>>,
+    Option<PendingSend>,
 );
 
+/// 排队或离线缓冲的 action, 实际发送后才开始计算响应超时
+///
+/// 限速排队的等待不超过 timeout, 离线缓冲的等待不超过 ttl
+#[derive(Debug)]
+pub(crate) struct PendingSend {
+    sent: oneshot::Receiver<()>,
+    expires: Option<Instant>,
+}
+
 impl Drop for EchoAsyncResponse {
     fn drop(&mut self) {
         self.2.remove(&self.0);
@@ -42,6 +101,27 @@ impl Drop for EchoAsyncResponse {
 
 impl EchoAsyncResponse {
     pub async fn response(mut self, timeout: Duration) -> Result<Response> {
+        if let Some(PendingSend { sent, expires }) = self.3.take() {
+            let sent = match expires {
+                Some(expires) => matches!(tokio::time::timeout_at(expires, sent).await, Ok(Ok(()))),
+                // 限速排队同样受 timeout 限制, 超时后 action 仍留在队列中
+                None => match tokio::time::timeout(timeout, sent).await {
+                    Ok(sent) => sent.is_ok(),
+                    Err(err) => {
+                        return Err(Error::TimeoutError(
+                            format!("action not sent before timeout : {}", self.0),
+                            err,
+                        ));
+                    }
+                },
+            };
+            if !sent {
+                return Err(Error::ConnectionLost(format!(
+                    "action not sent : {}",
+                    self.0
+                )));
+            }
+        }
         let r = tokio::time::timeout(timeout, async { self.1.recv().await }).await;
         let r = match r {
             Ok(r) => r,
@@ -52,9 +132,10 @@ impl EchoAsyncResponse {
                 ));
             }
         };
+        // 连接断开时 echo_notifer 被清空, sender 随之释放
         match r {
             Some(r) => Ok(r),
-            None => Err(Error::StateError(format!(
+            None => Err(Error::ConnectionLost(format!(
                 "response not received : {}",
                 self.0
             ))),
@@ -87,9 +168,8 @@ impl BotContext {
         msg: serde_json::Value,
     ) -> Result<EchoAsyncResponse> {
         let echo = uuid::Uuid::new_v4().to_string();
+        tracing::debug!(action, echo = %echo, "send action");
         let (sender, receiver) = tokio::sync::mpsc::channel::<Response>(1);
-        self.echo_notifer.insert(echo.clone(), sender);
-        let echo_response = EchoAsyncResponse(echo.clone(), receiver, self.echo_notifer.clone());
         let msg = json!(
             {
                 "action": action,
@@ -97,21 +177,249 @@ impl BotContext {
                 "echo": echo
             }
         );
+        let mut connection_lock = self.connection.lock().await;
+        let Some(connection) = connection_lock.as_mut() else {
+            return self.buffer_offline(action, echo, &msg, sender, receiver);
+        };
+        self.echo_notifer.insert(echo.clone(), sender);
+        if let Some(dispatcher) = &self.dispatcher {
+            dispatcher.notify_awaiting();
+        }
+        if let Some(metrics) = &self.metrics {
+            metrics.action_sent(action, &echo);
+        }
+        let mut echo_response = EchoAsyncResponse(
+            echo.clone(),
+            receiver,
+            self.echo_notifer.clone(),
+            None,
+        );
+        let sent = match &self.outbound {
+            Some(outbound) if outbound.is_queued(action) => {
+                let (sent, pending) = oneshot::channel();
+                echo_response.3 = Some(PendingSend {
+                    sent: pending,
+                    expires: None,
+                });
+                Some(sent)
+            }
+            _ => None,
+        };
+        let params = &msg["params"];
         let msg = serde_json::to_string(&msg).unwrap();
+        self.dispatch(connection, action, echo, msg, params, sent)
+            .await?;
+        Ok(echo_response)
+    }
+
+    /// 消息类 action 交给出站队列, 其他 action 直接发送
+    async fn dispatch(
+        &self,
+        connection: &mut BotConnection,
+        action: &str,
+        echo: String,
+        msg: String,
+        params: &serde_json::Value,
+        sent: Option<oneshot::Sender<()>>,
+    ) -> Result<()> {
+        if let Some(outbound) = &self.outbound
+            && outbound.is_queued(action)
+        {
+            let queued = QueuedAction {
+                action: action.to_string(),
+                echo,
+                msg,
+                sent,
+            };
+            outbound.enqueue(
+                params,
+                queued,
+                self.connection.clone(),
+                self.echo_notifer.clone(),
+            );
+            return Ok(());
+        }
         tracing::debug!("WS send: {}", msg);
-        let mut connection_lock = self.connection.lock().await;
-        let connection = connection_lock
-            .as_mut()
-            .ok_or(Error::StateError("connection not ready".to_string()))?;
         connection.send_raw(msg).await?;
-        Ok(echo_response)
+        if let Some(sent) = sent {
+            let _ = sent.send(());
+        }
+        Ok(())
+    }
+
+    /// 未连接时, 启用离线缓冲的 action 暂存到队列中, 其他 action 返回 NotConnected
+    fn buffer_offline(
+        &self,
+        action: &str,
+        echo: String,
+        msg: &serde_json::Value,
+        sender: tokio::sync::mpsc::Sender<Response>,
+        receiver: tokio::sync::mpsc::Receiver<Response>,
+    ) -> Result<EchoAsyncResponse> {
+        let Some(offline) = self.offline.as_ref().filter(|o| o.is_buffered(action)) else {
+            return Err(Error::NotConnected(action.to_string()));
+        };
+        let (sent, pending) = oneshot::channel();
+        let expires = offline.expires();
+        let buffered = OfflineAction {
+            action: action.to_string(),
+            echo: echo.clone(),
+            msg: serde_json::to_string(msg).unwrap(),
+            sender: Some(sender),
+            sent: Some(sent),
+            expires,
+        };
+        if offline.push(buffered).is_some() {
+            return Err(Error::NotConnected(format!(
+                "{} (offline buffer full)",
+                action
+            )));
+        }
+        tracing::debug!("WS offline buffered: {} {}", action, echo);
+        if let Some(metrics) = &self.metrics {
+            metrics.action_sent(action, &echo);
+        }
+        Ok(EchoAsyncResponse(
+            echo,
+            receiver,
+            self.echo_notifer.clone(),
+            Some(PendingSend {
+                sent: pending,
+                expires: Some(expires),
+            }),
+        ))
+    }
+
+    /// 重新连接后按顺序发送离线缓冲中的 action, 调用时持有连接的锁以保证顺序
+    async fn flush_offline(&self, connection: &mut BotConnection) {
+        let Some(offline) = &self.offline else {
+            return;
+        };
+        let count = offline.len();
+        if count > 0 {
+            tracing::info!("flush {} offline actions", count);
+        }
+        while let Some(mut buffered) = offline.pop() {
+            if let Some(sender) = buffered.sender.take()
+                && !sender.is_closed()
+            {
+                self.echo_notifer.insert(buffered.echo.clone(), sender);
+                if let Some(dispatcher) = &self.dispatcher {
+                    dispatcher.notify_awaiting();
+                }
+            }
+            if let Some(outbound) = &self.outbound
+                && outbound.is_queued(&buffered.action)
+            {
+                let params = serde_json::from_str::<serde_json::Value>(&buffered.msg)
+                    .map(|frame| frame["params"].clone())
+                    .unwrap_or_default();
+                let OfflineAction {
+                    action,
+                    echo,
+                    msg,
+                    sent,
+                    ..
+                } = buffered;
+                // 进入出站队列, 不会失败; 断线时由 fail_pending 放回离线缓冲
+                let _ = self
+                    .dispatch(connection, &action, echo, msg, &params, sent)
+                    .await;
+                continue;
+            }
+            tracing::debug!("WS send: {}", buffered.msg);
+            match connection.send_raw(buffered.msg.clone()).await {
+                Ok(()) => {
+                    if let Some(sent) = buffered.sent.take() {
+                        let _ = sent.send(());
+                    }
+                }
+                Err(err) => {
+                    // 放回队首, 下次连接时继续发送
+                    tracing::warn!("offline action {} not sent: {:?}", buffered.echo, err);
+                    buffered.sender = self.echo_notifer.remove(&buffered.echo).map(|(_, s)| s);
+                    offline.push_front([buffered].into_iter());
+                    break;
+                }
+            }
+        }
+    }
+
+    /// 离线缓冲中等待发送的 action 数量
+    pub fn offline_queue_len(&self) -> usize {
+        self.offline.as_ref().map_or(0, |offline| offline.len())
+    }
+
+    /// 出站队列中等待发送的 action 数量, 未启用出站调度时为 0
+    pub fn outbound_queue_depth(&self) -> usize {
+        self.outbound.as_ref().map_or(0, |outbound| outbound.depth())
+    }
+
+    /// 等待处理的事件数量, 未启用 `dispatch` 时为 0
+    pub fn dispatch_queue_depth(&self) -> usize {
+        self.dispatcher
+            .as_ref()
+            .map_or(0, |dispatcher| dispatcher.depth())
     }
 }
 
 impl BotContext {
     pub(crate) async fn set_connection(&self, connection: impl Into<Option<BotConnection>>) {
+        let connection = connection.into().map(|connection| self.with_recorder(connection));
+        let mut connection_lock = self.connection.lock().await;
+        *connection_lock = connection;
+        if let Some(connection) = connection_lock.as_mut() {
+            self.flush_offline(connection).await;
+        }
+    }
+
+    /// 仅当当前连接仍是 key 对应的连接时断开, 避免断开已被替换的新连接
+    pub(crate) async fn clear_connection(&self, key: u64) {
         let mut connection_lock = self.connection.lock().await;
-        *connection_lock = connection.into();
+        if connection_lock.as_ref().is_some_and(|c| c.key == key) {
+            *connection_lock = None;
+            self.fail_pending();
+        }
+    }
+
+    /// 等待中的 action 不会再收到响应, 立即以 ConnectionLost 结束
+    pub(crate) fn fail_pending(&self) {
+        if let Some(outbound) = &self.outbound {
+            let queued = outbound.clear();
+            // 出站队列中尚未发送的 action 放回离线缓冲, 重连后继续发送
+            if let Some(offline) = &self.offline {
+                let expires = offline.expires();
+                let buffered: Vec<_> = queued
+                    .into_iter()
+                    .filter(|queued| offline.is_buffered(&queued.action))
+                    .map(|queued| OfflineAction {
+                        sender: self.echo_notifer.remove(&queued.echo).map(|(_, s)| s),
+                        action: queued.action,
+                        echo: queued.echo,
+                        msg: queued.msg,
+                        sent: queued.sent,
+                        expires,
+                    })
+                    .collect();
+                if !buffered.is_empty() {
+                    offline.push_front(buffered.into_iter());
+                    // 这些 action 的 EchoAsyncResponse 没有期限, 到期时清理以结束等待
+                    offline.prune_at(expires);
+                }
+            }
+        }
+        if !self.echo_notifer.is_empty() {
+            tracing::warn!(
+                "connection lost, {} pending actions failed",
+                self.echo_notifer.len()
+            );
+            if let Some(metrics) = &self.metrics {
+                self.echo_notifer
+                    .iter()
+                    .for_each(|entry| metrics.forget(entry.key()));
+            }
+            self.echo_notifer.clear();
+        }
     }
 
     pub(crate) async fn handle_receive(
@@ -122,28 +430,123 @@ impl BotContext {
         match msg {
             tokio_tungstenite::tungstenite::protocol::Message::Text(text) => {
                 tracing::debug!("WS received: {}", text.to_string());
-                match parse_post(text) {
-                    Ok(post) => {
-                        tracing::debug!("parse post: {:?}", post);
-                        if let Post::Response(response) = &post {
-                            if let Some(v) = bot_ctx.echo_notifer.remove(&response.echo) {
-                                match v.1.send(response.clone()).await {
-                                    Ok(_) => {}
-                                    Err(err) => {
-                                        tracing::warn!("echo map error : {:?}", err);
-                                    }
-                                }
-                            }
-                        }
-                        let _ = loop_processors(bot_ctx, self.processors.iter(), &post).await;
+                self.record(Direction::In, text);
+                self.handle_text(bot_ctx, text).await;
+            }
+            // 部分实现以二进制帧发送 JSON
+            tokio_tungstenite::tungstenite::protocol::Message::Binary(data) => {
+                match std::str::from_utf8(data) {
+                    Ok(text) => {
+                        tracing::debug!("WS received binary: {}", text);
+                        self.record(Direction::In, text);
+                        self.handle_text(bot_ctx, text).await;
                     }
-                    Err(e) => {
-                        tracing::error!("Parse post error: {:?}", e);
+                    Err(err) => {
+                        tracing::warn!("WS received non utf-8 binary frame: {:?}", err);
                     }
                 }
             }
             _ => {
-                tracing::error!("WS received: {:?}", msg);
+                tracing::trace!("WS received: {:?}", msg);
+            }
+        }
+    }
+
+    /// 开启流量记录时, 连接在实际写出 action 后记录
+    fn with_recorder(&self, connection: BotConnection) -> BotConnection {
+        BotConnection {
+            recorder: self
+                .recorder
+                .clone()
+                .map(|recorder| (recorder, self.id.clone())),
+            ..connection
+        }
+    }
+
+    /// 开启流量记录时记录一帧
+    pub(crate) fn record(&self, direction: Direction, frame: &str) {
+        if let Some(recorder) = &self.recorder {
+            recorder.record(direction, self.id(), frame);
+        }
+    }
+
+    /// 处理一条 onebot 事件 / 响应 的 JSON 文本, ws 与 http post 共用
+    pub(crate) async fn handle_text(&self, bot_ctx: Arc<BotContext>, text: &str) {
+        let parsed = match serde_json::from_str::<serde_json::Value>(text) {
+            Ok(value) => Post::parse(&value).map(|post| (value, post)),
+            Err(e) => Err(e.into()),
+        };
+        match parsed {
+            Ok((value, post)) => {
+                let span = event_span(&value);
+                self.observe_post(&bot_ctx, &post)
+                    .instrument(span.clone())
+                    .await;
+                // 优雅关闭期间仍然接收响应, 但不再把事件交给处理器
+                if self.draining.load(Ordering::SeqCst) {
+                    span.in_scope(|| tracing::debug!("bot is draining, event skipped"));
+                    return;
+                }
+                let processors = self.processors.clone();
+                let middlewares = self.middlewares.clone();
+                let in_flight = InFlight::new(bot_ctx.clone());
+                let is_event = !matches!(post, Post::Response(_) | Post::MetaEvent(_));
+                let job = async move {
+                    let _in_flight = in_flight;
+                    let _ = loop_processors(bot_ctx, &processors, &middlewares, &post).await;
+                }
+                .instrument(span);
+                match &self.dispatcher {
+                    Some(dispatcher) if is_event => {
+                        let awaiting_response = || !self.echo_notifer.is_empty();
+                        dispatcher
+                            .dispatch(&value, Box::pin(job), awaiting_response)
+                            .await
+                    }
+                    // 响应与元事件不经过队列, 也不阻塞读取循环
+                    Some(_) => {
+                        tokio::spawn(job);
+                    }
+                    None => job.await,
+                }
+            }
+            Err(e) => {
+                if let Some(metrics) = &bot_ctx.metrics {
+                    metrics.parse_error();
+                }
+                tracing::error!("Parse post error: {:?}", e);
+            }
+        }
+    }
+
+    /// 事件交给处理器之前的内部处理, 不经过分发队列
+    async fn observe_post(&self, bot_ctx: &Arc<BotContext>, post: &Post) {
+        tracing::trace!("parse post: {:?}", post);
+        if let Some(metrics) = &bot_ctx.metrics {
+            metrics.event(post);
+        }
+        match post {
+            Post::MetaEvent(MetaEvent::Lifecycle(lifecycle)) => {
+                bot_ctx.set_id(lifecycle.self_id);
+                bot_ctx.hooks.lifecycle(bot_ctx, lifecycle);
+            }
+            Post::MetaEvent(MetaEvent::Heartbeat(heartbeat)) => {
+                bot_ctx.set_id(heartbeat.self_id);
+                bot_ctx.heartbeat.send_replace(Some(heartbeat.clone()));
+            }
+            _ => {}
+        }
+        if let Post::Response(response) = post {
+            if let Some(metrics) = &bot_ctx.metrics {
+                metrics.action_response(response);
+            }
+            if let Some(v) = bot_ctx.echo_notifer.remove(&response.echo) {
+                match v.1.send(response.clone()).await {
+                    Ok(_) => {}
+                    Err(err) => {
+                        tracing::warn!("echo map error : {:?}", err);
+                    }
+                }
             }
         }
     }
@@ -152,6 +555,26 @@ impl BotContext {
         self.processors.clone()
     }
 
+    /// 登录的账号 (self_id), 尚未确定时为 0
+    pub fn id(&self) -> i64 {
+        self.id.load(Ordering::Relaxed)
+    }
+
+    /// 最近一次收到的心跳
+    pub fn last_heartbeat(&self) -> Option<Heartbeat> {
+        self.heartbeat.borrow().clone()
+    }
+
+    pub(crate) fn set_id(&self, id: i64) {
+        if id == 0 {
+            return;
+        }
+        let old = self.id.swap(id, Ordering::Relaxed);
+        if old != id {
+            tracing::info!("bot self_id: {}", id);
+        }
+    }
+
     /// 检查是否已经 shutdown
     pub fn is_shutdown(&self) -> bool {
         // 如果 shutdown_rx 为 None，说明已经 shutdown
@@ -181,13 +604,101 @@ impl BotContext {
         }
         // 断开连接
         self.set_connection(None).await;
+        self.fail_pending();
+        if let Some(offline) = &self.offline {
+            offline.clear();
+        }
         Ok(())
     }
+
+    /// 正在运行以及在分发队列中等待的处理器链数量
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::SeqCst)
+    }
+
+    /// 优雅关闭: 不再把新的事件交给处理器, 等待正在运行的处理器、等待响应的 action
+    /// 以及出站队列和离线缓冲中尚未发送的 action, 最多等待 `drain_timeout`, 然后关闭连接
+    pub async fn graceful_shutdown(&self, drain_timeout: Duration) -> Result<()> {
+        if !self.draining.swap(true, Ordering::SeqCst) {
+            tracing::info!("Bot {} draining", self.id());
+        }
+        let deadline = Instant::now() + drain_timeout;
+        while self.in_flight() > 0
+            || !self.echo_notifer.is_empty()
+            || self.outbound_queue_depth() > 0
+            || self.offline_queue_len() > 0
+        {
+            if Instant::now() >= deadline {
+                tracing::warn!(
+                    "Bot {} drain timeout, {} processors, {} actions waiting for response and {} actions unsent",
+                    self.id(),
+                    self.in_flight(),
+                    self.echo_notifer.len(),
+                    self.outbound_queue_depth() + self.offline_queue_len()
+                );
+                break;
+            }
+            tokio::time::sleep(DRAIN_POLL_INTERVAL).await;
+        }
+        self.shutdown().await
+    }
+}
+
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// 计入 `in_flight` 的处理器链, 结束或在队列中被丢弃时释放
+struct InFlight(Arc<BotContext>);
+
+impl InFlight {
+    fn new(bot_ctx: Arc<BotContext>) -> Self {
+        bot_ctx.in_flight.fetch_add(1, Ordering::SeqCst);
+        Self(bot_ctx)
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.in_flight.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// 每个事件一个 span, 处理器以及其中发送的 action 都记录在这个 span 下
+fn event_span(value: &serde_json::Value) -> tracing::Span {
+    let span = tracing::info_span!(
+        "event",
+        post_type = value["post_type"].as_str().unwrap_or("response"),
+        detail_type = Empty,
+        self_id = Empty,
+        group_id = Empty,
+        user_id = Empty,
+        message_id = Empty,
+        echo = Empty,
+    );
+    if let Some(detail_type) = ["message_type", "notice_type", "request_type", "meta_event_type"]
+        .iter()
+        .find_map(|key| value[key].as_str())
+    {
+        span.record("detail_type", detail_type);
+    }
+    for key in ["self_id", "group_id", "user_id", "message_id"] {
+        if let Some(id) = value[key].as_i64() {
+            span.record(key, id);
+        }
+    }
+    if let Some(echo) = value["echo"].as_str() {
+        span.record("echo", echo);
+    }
+    span
 }
 
 #[async_trait]
 pub trait WsWriter {
     async fn send_raw(&mut self, msg: String) -> Result<()>;
+
+    /// 发送 ping 保持连接, 不支持的传输方式忽略
+    async fn send_ping(&mut self) -> Result<()> {
+        Ok(())
+    }
 }
 
 // 泛型实现
@@ -204,36 +715,238 @@ where
         .await?;
         Ok(())
     }
+
+    async fn send_ping(&mut self) -> Result<()> {
+        self.send(tokio_tungstenite::tungstenite::protocol::Message::Ping(
+            Default::default(),
+        ))
+        .await?;
+        Ok(())
+    }
+}
+
+/// onebot http api 的 action 发送者
+///
+/// 每个 action 以 `POST /<action>` 调用, 响应体补全 echo 后投递给 echo_notifer,
+/// 因此 `EchoAsyncResponse` 与 ws 下的用法完全一致
+pub(crate) struct HttpWriter {
+    api_url: String,
+    access_token: Option<String>,
+    client: Client<HttpConnector, Full<Bytes>>,
+    echo_notifer: Arc<DashMap<String, tokio::sync::mpsc::Sender<Response>>>,
+    metrics: Option<Arc<Metrics>>,
+}
+
+impl HttpWriter {
+    pub(crate) fn new(
+        api_url: impl Into<String>,
+        access_token: Option<String>,
+        echo_notifer: Arc<DashMap<String, tokio::sync::mpsc::Sender<Response>>>,
+        metrics: Option<Arc<Metrics>>,
+    ) -> Self {
+        Self {
+            api_url: api_url.into().trim_end_matches('/').to_string(),
+            access_token,
+            client: Client::builder(TokioExecutor::new()).build_http(),
+            echo_notifer,
+            metrics,
+        }
+    }
+
+    async fn call(
+        client: Client<HttpConnector, Full<Bytes>>,
+        request: hyper::Request<Full<Bytes>>,
+    ) -> Result<serde_json::Value> {
+        let response = client
+            .request(request)
+            .await
+            .map_err(|e| Error::HttpError(e.to_string()))?;
+        let status = response.status();
+        if !status.is_success() {
+            return Err(Error::HttpError(format!("http status {}", status)));
+        }
+        let body = response
+            .into_body()
+            .collect()
+            .await
+            .map_err(|e| Error::HttpError(e.to_string()))?
+            .to_bytes();
+        Ok(serde_json::from_slice(&body)?)
+    }
+}
+
+#[async_trait]
+impl WsWriter for HttpWriter {
+    async fn send_raw(&mut self, msg: String) -> Result<()> {
+        let frame: serde_json::Value = serde_json::from_str(&msg)?;
+        let action = frame
+            .get("action")
+            .and_then(|v| v.as_str())
+            .ok_or(Error::FieldError("action not found".to_string()))?
+            .to_string();
+        let echo = frame
+            .get("echo")
+            .and_then(|v| v.as_str())
+            .ok_or(Error::FieldError("echo not found".to_string()))?
+            .to_string();
+        let body = match frame.get("params") {
+            Some(params) if !params.is_null() => serde_json::to_vec(params)?,
+            _ => b"{}".to_vec(),
+        };
+        let mut request = hyper::Request::post(format!("{}/{}", self.api_url, action))
+            .header(hyper::header::CONTENT_TYPE, "application/json");
+        if let Some(access_token) = &self.access_token {
+            request = request.header(
+                hyper::header::AUTHORIZATION,
+                format!("Bearer {}", access_token),
+            );
+        }
+        let request = request
+            .body(Full::new(Bytes::from(body)))
+            .map_err(|e| Error::HttpError(e.to_string()))?;
+        let client = self.client.clone();
+        let echo_notifer = self.echo_notifer.clone();
+        let metrics = self.metrics.clone();
+        tokio::spawn(async move {
+            let response = match Self::call(client, request).await {
+                Ok(mut value) => {
+                    // http 响应没有 echo, 部分实现也不返回 message / wording
+                    if let Some(object) = value.as_object_mut() {
+                        object.insert("echo".to_string(), json!(echo));
+                        object.entry("message").or_insert(json!(""));
+                        object.entry("wording").or_insert(json!(""));
+                    }
+                    Response::parse(&value)
+                }
+                Err(err) => Err(err),
+            };
+            match response {
+                Ok(response) => {
+                    tracing::debug!("HTTP received: {:?}", response);
+                    if let Some(metrics) = &metrics {
+                        metrics.action_response(&response);
+                    }
+                    if let Some(v) = echo_notifer.remove(&echo)
+                        && let Err(err) = v.1.send(response).await
+                    {
+                        tracing::warn!("echo map error : {:?}", err);
+                    }
+                }
+                Err(err) => {
+                    tracing::error!("HTTP action {} error: {:?}", action, err);
+                    // 以失败的响应结束等待, 而不是 ConnectionLost
+                    let response = Response {
+                        status: "failed".to_string(),
+                        retcode: -1,
+                        data: serde_json::Value::Null,
+                        message: err.to_string(),
+                        wording: err.to_string(),
+                        echo: echo.clone(),
+                    };
+                    if let Some(metrics) = &metrics {
+                        metrics.action_response(&response);
+                    }
+                    if let Some(v) = echo_notifer.remove(&echo) {
+                        let _ = v.1.send(response).await;
+                    }
+                }
+            }
+        });
+        Ok(())
+    }
 }
 
 pub(crate) struct BotConnection {
+    pub(crate) key: u64,
     pub(crate) sender: Box<dyn WsWriter + Send + Sync>,
+    // 流量记录与 bot 的账号
+    recorder: Option<(Arc<TrafficRecorder>, Arc<AtomicI64>)>,
 }
 
+static NEXT_CONNECTION_KEY: AtomicU64 = AtomicU64::new(1);
+
 impl BotConnection {
+    pub(crate) fn new(sender: Box<dyn WsWriter + Send + Sync>) -> Self {
+        Self {
+            key: NEXT_CONNECTION_KEY.fetch_add(1, Ordering::Relaxed),
+            sender,
+            recorder: None,
+        }
+    }
+
     pub async fn send_raw(&mut self, msg: String) -> Result<()> {
+        let Some((recorder, bot_id)) = &self.recorder else {
+            return self.sender.send_raw(msg).await;
+        };
+        let frame = msg.clone();
         self.sender.send_raw(msg).await?;
+        recorder.record(Direction::Out, bot_id.load(Ordering::Relaxed), &frame);
         Ok(())
     }
+
+    pub async fn send_ping(&mut self) -> Result<()> {
+        self.sender.send_ping().await
+    }
 }
 
 impl fmt::Debug for BotConnection {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("BotConnection")
+            .field("key", &self.key)
             .field("sender", &"Box<dyn WsWriter>")
             .finish()
     }
 }
 pub struct BotContextBuilder {
     pub url: Option<String>,
+    pub unix_socket: Option<PathBuf>,
+    pub proxy: Option<Proxy>,
+    pub http_api_url: Option<String>,
+    pub http_post_bind: Option<String>,
+    pub access_token: Option<String>,
+    pub secret: Option<String>,
+    pub reconnect_policy: ReconnectPolicy,
+    pub reconnect_observer: Option<ReconnectObserver>,
+    pub tls: Option<ClientTlsConfig>,
+    pub heartbeat_watchdog: Option<HeartbeatWatchdog>,
+    pub outbound: Option<OutboundPolicy>,
+    pub dispatch: Option<DispatchPolicy>,
+    pub offline_buffer: Option<OfflineBuffer>,
+    pub hooks: ConnectionHooks,
+    pub record_traffic: Option<PathBuf>,
+    pub metrics: Option<Arc<Metrics>>,
+    pub ping_interval: Option<Duration>,
+    pub pong_timeout: Duration,
     pub processors: Vec<Processor>,
+    pub middlewares: Middlewares,
+    pub error_policy: ErrorPolicy,
 }
 
 impl BotContextBuilder {
     pub fn new() -> Self {
         Self {
             url: None,
+            unix_socket: None,
+            proxy: None,
+            http_api_url: None,
+            http_post_bind: None,
+            access_token: None,
+            secret: None,
+            reconnect_policy: ReconnectPolicy::default(),
+            reconnect_observer: None,
+            tls: None,
+            heartbeat_watchdog: None,
+            outbound: None,
+            dispatch: None,
+            offline_buffer: None,
+            hooks: ConnectionHooks::default(),
+            record_traffic: None,
+            metrics: None,
+            ping_interval: None,
+            pong_timeout: Duration::from_secs(10),
             processors: vec![],
+            middlewares: Middlewares::default(),
+            error_policy: ErrorPolicy::default(),
         }
     }
 
@@ -244,6 +957,175 @@ impl BotContextBuilder {
         }
     }
 
+    /// 通过 unix socket 连接正向ws, 此时 url 只用于握手请求, 可以不设置
+    pub fn unix_socket(self, unix_socket: impl Into<PathBuf>) -> Self {
+        Self {
+            unix_socket: Some(unix_socket.into()),
+            ..self
+        }
+    }
+
+    /// 正向ws 通过 HTTP CONNECT 或 SOCKS5 代理连接
+    pub fn proxy(self, proxy: Proxy) -> Self {
+        Self {
+            proxy: Some(proxy),
+            ..self
+        }
+    }
+
+    /// onebot http api 地址, 例如 `http://localhost:3000`, 设置后 action 通过 http 调用
+    pub fn http_api_url(self, http_api_url: impl Into<String>) -> Self {
+        Self {
+            http_api_url: Some(http_api_url.into()),
+            ..self
+        }
+    }
+
+    /// http post 事件上报的监听地址, 例如 `0.0.0.0:5700`, 配合 `loop_http_post` 使用
+    pub fn http_post_bind(self, http_post_bind: impl Into<String>) -> Self {
+        Self {
+            http_post_bind: Some(http_post_bind.into()),
+            ..self
+        }
+    }
+
+    /// onebot access_token, 连接 ws 与调用 http api 时以 `Authorization: Bearer` 发送
+    pub fn access_token(self, access_token: impl Into<String>) -> Self {
+        Self {
+            access_token: Some(access_token.into()),
+            ..self
+        }
+    }
+
+    /// onebot secret, 设置后 http post 上报的事件必须携带正确的 `X-Signature`
+    pub fn secret(self, secret: impl Into<String>) -> Self {
+        Self {
+            secret: Some(secret.into()),
+            ..self
+        }
+    }
+
+    /// loop_client 断线后的重连策略, 默认每 15 秒重连一次
+    pub fn reconnect_policy(self, reconnect_policy: ReconnectPolicy) -> Self {
+        Self {
+            reconnect_policy,
+            ..self
+        }
+    }
+
+    /// 每次准备重连时调用, 可用于记录或告警
+    pub fn on_reconnect(
+        self,
+        on_reconnect: impl Fn(&ReconnectAttempt) + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            reconnect_observer: Some(ReconnectObserver(Arc::new(on_reconnect))),
+            ..self
+        }
+    }
+
+    /// `wss://` 连接的 TLS 设置, 不设置时使用 webpki 内置根证书
+    pub fn tls(self, tls: ClientTlsConfig) -> Self {
+        Self {
+            tls: Some(tls),
+            ..self
+        }
+    }
+
+    /// 启用心跳看门狗, 长时间收不到心跳时断开并重连
+    pub fn heartbeat_watchdog(self, heartbeat_watchdog: HeartbeatWatchdog) -> Self {
+        Self {
+            heartbeat_watchdog: Some(heartbeat_watchdog),
+            ..self
+        }
+    }
+
+    /// 启用出站调度, 消息类 action 排队并按限速发送
+    pub fn outbound(self, outbound: OutboundPolicy) -> Self {
+        Self {
+            outbound: Some(outbound),
+            ..self
+        }
+    }
+
+    /// 限制处理器的并发数量, 可以让同一会话的事件按顺序处理
+    pub fn dispatch(self, dispatch: DispatchPolicy) -> Self {
+        Self {
+            dispatch: Some(dispatch),
+            ..self
+        }
+    }
+
+    /// 把收发的每一帧追加写入 JSONL 文件, 可以用 `TrafficReplayer` 回放
+    pub fn record_traffic(self, path: impl Into<PathBuf>) -> Self {
+        Self {
+            record_traffic: Some(path.into()),
+            ..self
+        }
+    }
+
+    /// 统计事件、处理器与 action, 可以用 `serve_metrics` 提供 Prometheus 抓取
+    pub fn metrics(self, metrics: Arc<Metrics>) -> Self {
+        Self {
+            metrics: Some(metrics),
+            ..self
+        }
+    }
+
+    /// 启用离线缓冲, 断线期间的消息在重连后发送
+    pub fn offline_buffer(self, offline_buffer: OfflineBuffer) -> Self {
+        Self {
+            offline_buffer: Some(offline_buffer),
+            ..self
+        }
+    }
+
+    /// 定时发送 ws ping, 避免空闲连接被 NAT 回收
+    pub fn ping_interval(self, ping_interval: Duration) -> Self {
+        Self {
+            ping_interval: Some(ping_interval),
+            ..self
+        }
+    }
+
+    /// 发送 ping 后等待 pong 的时间, 超时后断开并重连, 默认 10 秒
+    pub fn pong_timeout(self, pong_timeout: Duration) -> Self {
+        Self {
+            pong_timeout,
+            ..self
+        }
+    }
+
+    /// 连接建立后调用 (仅可发送 action 的连接)
+    pub fn on_connect<F, Fut>(mut self, on_connect: F) -> Self
+    where
+        F: Fn(Arc<BotContext>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.hooks.set_on_connect(on_connect);
+        self
+    }
+
+    /// 连接断开后调用 (仅可发送 action 的连接)
+    pub fn on_disconnect<F, Fut>(mut self, on_disconnect: F) -> Self
+    where
+        F: Fn(Arc<BotContext>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.hooks.set_on_disconnect(on_disconnect);
+        self
+    }
+
+    /// 收到 lifecycle 元事件 (enable / disable / connect) 时调用
+    pub fn on_lifecycle<F, Fut>(mut self, on_lifecycle: F) -> Self
+    where
+        F: Fn(Arc<BotContext>, Lifecycle) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.hooks.set_on_lifecycle(on_lifecycle);
+        self
+    }
+
     pub fn add_processor(
         mut self,
         processor: impl Into<Processor> + Sync + Send + 'static,
@@ -252,14 +1134,88 @@ impl BotContextBuilder {
         self
     }
 
+    /// 添加包裹整个处理器链的中间件, 先添加的在外层
+    pub fn middleware(mut self, middleware: impl Middleware + 'static) -> Self {
+        self.middlewares.chain.push(Arc::new(middleware));
+        self
+    }
+
+    /// 添加包裹每个处理器的中间件, 先添加的在外层
+    pub fn processor_middleware(mut self, middleware: impl Middleware + 'static) -> Self {
+        self.middlewares.processor.push(Arc::new(middleware));
+        self
+    }
+
+    /// 处理器出错时的策略, 默认 `UserError` 回复给用户, 其他错误停止处理器链
+    pub fn error_policy(self, error_policy: ErrorPolicy) -> Self {
+        Self {
+            error_policy,
+            ..self
+        }
+    }
+
     pub fn build(self) -> Result<Arc<BotContext>> {
+        if let Some(path) = &self.unix_socket
+            && cfg!(not(unix))
+        {
+            return Err(unix_socket_unsupported(path));
+        }
+        let wss = self.url.as_ref().is_some_and(|url| url.starts_with("wss://"));
+        let tls_config = match self.tls {
+            Some(tls) => Some(tls.build()?),
+            None if wss => Some(ClientTlsConfig::default().build()?),
+            None => None,
+        };
         let (shutdown_tx, shutdown_rx) = tokio::sync::watch::channel(false);
+        let echo_notifer = Arc::new(DashMap::new());
+        if let Some(metrics) = &self.metrics {
+            metrics.watch_echoes(&echo_notifer);
+        }
+        let recorder = match self.record_traffic {
+            Some(path) => Some(Arc::new(TrafficRecorder::open(path)?)),
+            None => None,
+        };
+        let id = Arc::new(AtomicI64::new(0));
+        let connection = self.http_api_url.as_ref().map(|http_api_url| BotConnection {
+            recorder: recorder.clone().map(|recorder| (recorder, id.clone())),
+            ..BotConnection::new(Box::new(HttpWriter::new(
+                http_api_url,
+                self.access_token.clone(),
+                echo_notifer.clone(),
+                self.metrics.clone(),
+            )))
+        });
         Ok(Arc::new(BotContext {
-            connection: Mutex::new(None),
+            connection: Arc::new(Mutex::new(connection)),
             url: self.url,
-            id: 0,
+            unix_socket: self.unix_socket,
+            proxy: self.proxy,
+            http_api_url: self.http_api_url,
+            http_post_bind: self.http_post_bind,
+            access_token: self.access_token,
+            secret: self.secret,
+            reconnect_policy: self.reconnect_policy,
+            reconnect_observer: self.reconnect_observer,
+            tls_config,
+            heartbeat_watchdog: self.heartbeat_watchdog,
+            heartbeat: tokio::sync::watch::channel(None).0,
+            outbound: self.outbound.map(|policy| Arc::new(Outbound::new(policy))),
+            dispatcher: self.dispatch.map(|policy| Arc::new(Dispatcher::new(policy))),
+            offline: self
+                .offline_buffer
+                .map(|offline_buffer| Arc::new(OfflineQueue::new(offline_buffer))),
+            hooks: self.hooks,
+            recorder,
+            metrics: self.metrics,
+            ping_interval: self.ping_interval,
+            pong_timeout: self.pong_timeout,
+            id,
+            draining: AtomicBool::new(false),
+            in_flight: AtomicUsize::new(0),
             processors: Arc::new(self.processors),
-            echo_notifer: Arc::new(DashMap::new()),
+            middlewares: Arc::new(self.middlewares),
+            error_policy: self.error_policy,
+            echo_notifer,
             shutdown_tx: Mutex::new(Some(shutdown_tx)),
             shutdown_rx: Mutex::new(Some(shutdown_rx)),
         }))
@@ -268,27 +1224,348 @@ impl BotContextBuilder {
 
 pub struct BotServer {
     pub bind: String,
+    pub unix_socket: Option<PathBuf>,
+    pub unix_socket_mode: Option<u32>,
+    pub access_token: Option<String>,
+    pub(crate) tls_acceptor: Option<tokio_rustls::TlsAcceptor>,
+    pub heartbeat_watchdog: Option<HeartbeatWatchdog>,
+    pub outbound: Option<OutboundPolicy>,
+    pub dispatch: Option<DispatchPolicy>,
+    pub hooks: ConnectionHooks,
+    pub(crate) recorder: Option<Arc<TrafficRecorder>>,
+    pub metrics: Option<Arc<Metrics>>,
     pub processors: Arc<Vec<Processor>>,
+    pub(crate) middlewares: Arc<Middlewares>,
+    pub(crate) error_policy: ErrorPolicy,
+    pub(crate) manager: Arc<BotManager>,
+    pub(crate) split_bots: DashMap<i64, SplitBot>,
+    pub(crate) events: tokio::sync::broadcast::Sender<BotServerEvent>,
+    pub(crate) shutdown_tx: Mutex<Option<tokio::sync::watch::Sender<bool>>>,
+    pub(crate) shutdown_rx: Mutex<Option<tokio::sync::watch::Receiver<bool>>>,
+}
+
+/// 同一账号通过多个 API / Event socket 连接时共享的上下文
+pub(crate) struct SplitBot {
+    bot_ctx: Arc<BotContext>,
+    sockets: usize,
+}
+
+/// 反向ws 连接的上下线事件, 通过 `BotServer::subscribe` 订阅
+#[derive(Debug, Clone)]
+pub enum BotServerEvent {
+    Connected(Arc<BotContext>),
+    Disconnected(Arc<BotContext>),
+}
+
+impl BotServer {
+    /// 为一个新的反向ws 连接创建 bot 上下文
+    pub(crate) fn new_bot_context(&self, id: i64) -> Arc<BotContext> {
+        let (shutdown_tx, shutdown_rx) = tokio::sync::watch::channel(false);
+        let echo_notifer = Arc::new(DashMap::new());
+        if let Some(metrics) = &self.metrics {
+            metrics.watch_echoes(&echo_notifer);
+        }
+        Arc::new(BotContext {
+            connection: Arc::new(Mutex::new(None)),
+            url: None,
+            unix_socket: None,
+            proxy: None,
+            http_api_url: None,
+            http_post_bind: None,
+            access_token: None,
+            secret: None,
+            reconnect_policy: ReconnectPolicy::default(),
+            reconnect_observer: None,
+            tls_config: None,
+            heartbeat_watchdog: self.heartbeat_watchdog.clone(),
+            heartbeat: tokio::sync::watch::channel(None).0,
+            outbound: self
+                .outbound
+                .clone()
+                .map(|policy| Arc::new(Outbound::new(policy))),
+            dispatcher: self
+                .dispatch
+                .clone()
+                .map(|policy| Arc::new(Dispatcher::new(policy))),
+            offline: None,
+            hooks: self.hooks.clone(),
+            recorder: self.recorder.clone(),
+            metrics: self.metrics.clone(),
+            ping_interval: None,
+            pong_timeout: Duration::from_secs(10),
+            id: Arc::new(AtomicI64::new(id)),
+            draining: AtomicBool::new(false),
+            in_flight: AtomicUsize::new(0),
+            processors: self.processors.clone(),
+            middlewares: self.middlewares.clone(),
+            error_policy: self.error_policy.clone(),
+            echo_notifer,
+            shutdown_tx: Mutex::new(Some(shutdown_tx)),
+            shutdown_rx: Mutex::new(Some(shutdown_rx)),
+        })
+    }
+
+    /// 为一个反向ws socket 获取 bot 上下文
+    ///
+    /// Universal 或未知账号的连接独占一个上下文, 同一 X-Self-ID 的 API 与 Event 连接共享一个上下文
+    pub(crate) fn attach(&self, self_id: i64, role: ClientRole) -> Arc<BotContext> {
+        if role == ClientRole::Universal || self_id == 0 {
+            let bot_ctx = self.new_bot_context(self_id);
+            self.register(bot_ctx.clone());
+            return bot_ctx;
+        }
+        let mut created = false;
+        let bot_ctx = {
+            let mut split_bot = self.split_bots.entry(self_id).or_insert_with(|| {
+                created = true;
+                SplitBot {
+                    bot_ctx: self.new_bot_context(self_id),
+                    sockets: 0,
+                }
+            });
+            split_bot.sockets += 1;
+            split_bot.bot_ctx.clone()
+        };
+        if created {
+            self.register(bot_ctx.clone());
+        }
+        bot_ctx
+    }
+
+    /// socket 断开, 上下文的所有 socket 都断开后注销
+    pub(crate) fn detach(&self, bot_ctx: &Arc<BotContext>) {
+        let self_id = bot_ctx.id();
+        let removed = self
+            .split_bots
+            .remove_if_mut(&self_id, |_, split_bot| {
+                if Arc::ptr_eq(&split_bot.bot_ctx, bot_ctx) {
+                    split_bot.sockets -= 1;
+                }
+                split_bot.sockets == 0
+            })
+            .is_some();
+        let shared = self
+            .split_bots
+            .get(&self_id)
+            .is_some_and(|split_bot| Arc::ptr_eq(&split_bot.bot_ctx, bot_ctx));
+        if removed || !shared {
+            self.unregister(bot_ctx);
+        }
+    }
+
+    pub(crate) fn register(&self, bot_ctx: Arc<BotContext>) {
+        self.manager.add(bot_ctx.clone());
+        let _ = self.events.send(BotServerEvent::Connected(bot_ctx));
+    }
+
+    pub(crate) fn unregister(&self, bot_ctx: &Arc<BotContext>) {
+        if let Some(bot_ctx) = self.manager.remove(bot_ctx) {
+            let _ = self.events.send(BotServerEvent::Disconnected(bot_ctx));
+        }
+    }
+
+    /// 已连接的 bot 均注册在 manager 中, 可以按账号查找
+    pub fn manager(&self) -> Arc<BotManager> {
+        self.manager.clone()
+    }
+
+    /// 根据账号查找已连接的 bot
+    pub fn bot(&self, self_id: i64) -> Option<Arc<BotContext>> {
+        self.manager.bot(self_id)
+    }
+
+    /// 当前已连接的所有 bot 上下文, 可用于定时任务等主动发送
+    pub fn bots(&self) -> Vec<Arc<BotContext>> {
+        self.manager.bots()
+    }
+
+    /// 订阅连接的上下线事件
+    pub fn subscribe(&self) -> tokio::sync::broadcast::Receiver<BotServerEvent> {
+        self.events.subscribe()
+    }
+
+    /// 检查是否已经 shutdown
+    pub fn is_shutdown(&self) -> bool {
+        let shutdown_rx_guard = self.shutdown_rx.try_lock();
+        if let Ok(shutdown_rx) = shutdown_rx_guard {
+            match shutdown_rx.as_ref() {
+                None => return true,
+                Some(receiver) => return *receiver.borrow(),
+            }
+        }
+        false
+    }
+
+    async fn stop_listening(&self) -> Result<()> {
+        let mut shutdown_tx = self.shutdown_tx.lock().await;
+        if let Some(ref sender) = *shutdown_tx {
+            sender.send(true).map_err(|_| {
+                Error::StateError("Failed to send shutdown signal".to_string())
+            })?;
+            *shutdown_tx = None;
+            let mut shutdown_rx = self.shutdown_rx.lock().await;
+            *shutdown_rx = None;
+        }
+        Ok(())
+    }
+
+    /// 停止监听, 并关闭所有已连接的 bot
+    pub async fn shutdown(&self) -> Result<()> {
+        self.stop_listening().await?;
+        for bot_ctx in self.bots() {
+            bot_ctx.shutdown().await?;
+        }
+        Ok(())
+    }
+
+    /// 停止监听, 并优雅关闭所有已连接的 bot, 见 `BotContext::graceful_shutdown`
+    pub async fn graceful_shutdown(&self, drain_timeout: Duration) -> Result<()> {
+        self.stop_listening().await?;
+        let bots = self.bots();
+        futures_util::future::join_all(
+            bots.iter()
+                .map(|bot_ctx| bot_ctx.graceful_shutdown(drain_timeout)),
+        )
+        .await
+        .into_iter()
+        .collect()
+    }
 }
 
 pub struct BotServerBuilder {
     pub bind: Option<String>,
+    pub unix_socket: Option<PathBuf>,
+    pub unix_socket_mode: Option<u32>,
+    pub access_token: Option<String>,
+    pub tls: Option<ServerTlsConfig>,
+    pub heartbeat_watchdog: Option<HeartbeatWatchdog>,
+    pub outbound: Option<OutboundPolicy>,
+    pub dispatch: Option<DispatchPolicy>,
+    pub hooks: ConnectionHooks,
+    pub record_traffic: Option<PathBuf>,
+    pub metrics: Option<Arc<Metrics>>,
+    pub manager: Option<Arc<BotManager>>,
     pub processors: Vec<Processor>,
+    pub middlewares: Middlewares,
+    pub error_policy: ErrorPolicy,
 }
 
 impl BotServerBuilder {
     pub fn new() -> Self {
         Self {
             bind: None,
+            unix_socket: None,
+            unix_socket_mode: None,
+            access_token: None,
+            tls: None,
+            heartbeat_watchdog: None,
+            outbound: None,
+            dispatch: None,
+            hooks: ConnectionHooks::default(),
+            record_traffic: None,
+            metrics: None,
+            manager: None,
             processors: vec![],
+            middlewares: Middlewares::default(),
+            error_policy: ErrorPolicy::default(),
         }
     }
 
+    /// 设置证书与私钥后以 `wss://` 提供服务
+    pub fn tls(mut self, tls: ServerTlsConfig) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    /// 启用心跳看门狗, 长时间收不到心跳的连接将被断开
+    pub fn heartbeat_watchdog(mut self, heartbeat_watchdog: HeartbeatWatchdog) -> Self {
+        self.heartbeat_watchdog = Some(heartbeat_watchdog);
+        self
+    }
+
+    /// 启用出站调度, 每个连接的 bot 拥有独立的队列
+    pub fn outbound(mut self, outbound: OutboundPolicy) -> Self {
+        self.outbound = Some(outbound);
+        self
+    }
+
+    /// 限制处理器的并发数量, 每个连接的 bot 拥有独立的队列
+    pub fn dispatch(mut self, dispatch: DispatchPolicy) -> Self {
+        self.dispatch = Some(dispatch);
+        self
+    }
+
+    /// 每个反向ws 连接建立后调用 (仅可发送 action 的连接)
+    pub fn on_connect<F, Fut>(mut self, on_connect: F) -> Self
+    where
+        F: Fn(Arc<BotContext>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.hooks.set_on_connect(on_connect);
+        self
+    }
+
+    /// 连接断开后调用 (仅可发送 action 的连接)
+    pub fn on_disconnect<F, Fut>(mut self, on_disconnect: F) -> Self
+    where
+        F: Fn(Arc<BotContext>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.hooks.set_on_disconnect(on_disconnect);
+        self
+    }
+
+    /// 收到 lifecycle 元事件 (enable / disable / connect) 时调用
+    pub fn on_lifecycle<F, Fut>(mut self, on_lifecycle: F) -> Self
+    where
+        F: Fn(Arc<BotContext>, Lifecycle) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.hooks.set_on_lifecycle(on_lifecycle);
+        self
+    }
+
+    /// 把所有 bot 收发的帧追加写入 JSONL 文件, 可以用 `TrafficReplayer` 回放
+    pub fn record_traffic(mut self, path: impl Into<PathBuf>) -> Self {
+        self.record_traffic = Some(path.into());
+        self
+    }
+
+    /// 统计所有 bot 的事件、处理器与 action, 可以用 `serve_metrics` 提供 Prometheus 抓取
+    pub fn metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// 使用外部的 BotManager, 便于与正向ws 的 bot 统一管理
+    pub fn manager(mut self, manager: Arc<BotManager>) -> Self {
+        self.manager = Some(manager);
+        self
+    }
+
     pub fn bind(mut self, bind: impl Into<String>) -> Self {
         self.bind = Some(bind.into());
         self
     }
 
+    /// 监听 unix socket 而不是 tcp 端口, 设置后可以不设置 bind
+    pub fn unix_socket(mut self, unix_socket: impl Into<PathBuf>) -> Self {
+        self.unix_socket = Some(unix_socket.into());
+        self
+    }
+
+    /// unix socket 文件的权限, 例如 `0o660`
+    pub fn unix_socket_mode(mut self, mode: u32) -> Self {
+        self.unix_socket_mode = Some(mode);
+        self
+    }
+
+    /// 设置后, 握手时未携带或携带错误 access_token 的连接将被拒绝
+    pub fn access_token(mut self, access_token: impl Into<String>) -> Self {
+        self.access_token = Some(access_token.into());
+        self
+    }
+
     pub fn add_processor(
         mut self,
         processor: impl Into<Processor> + Sync + Send + 'static,
@@ -297,14 +1574,346 @@ impl BotServerBuilder {
         self
     }
 
+    /// 添加包裹整个处理器链的中间件, 先添加的在外层
+    pub fn middleware(mut self, middleware: impl Middleware + 'static) -> Self {
+        self.middlewares.chain.push(Arc::new(middleware));
+        self
+    }
+
+    /// 添加包裹每个处理器的中间件, 先添加的在外层
+    pub fn processor_middleware(mut self, middleware: impl Middleware + 'static) -> Self {
+        self.middlewares.processor.push(Arc::new(middleware));
+        self
+    }
+
+    /// 处理器出错时的策略, 默认 `UserError` 回复给用户, 其他错误停止处理器链
+    pub fn error_policy(mut self, error_policy: ErrorPolicy) -> Self {
+        self.error_policy = error_policy;
+        self
+    }
+
     pub fn build(self) -> Result<Arc<BotServer>> {
+        if let Some(path) = &self.unix_socket
+            && cfg!(not(unix))
+        {
+            return Err(unix_socket_unsupported(path));
+        }
+        let (shutdown_tx, shutdown_rx) = tokio::sync::watch::channel(false);
         Ok(Arc::new(BotServer {
-            bind: if let Some(bind) = self.bind {
-                bind
-            } else {
-                return Err(Error::ParamsError("bind must be set".to_string()));
+            bind: match (self.bind, &self.unix_socket) {
+                (Some(bind), _) => bind,
+                (None, Some(unix_socket)) => format!("unix:{}", unix_socket.display()),
+                (None, None) => {
+                    return Err(Error::ParamsError("bind must be set".to_string()));
+                }
             },
+            unix_socket: self.unix_socket,
+            unix_socket_mode: self.unix_socket_mode,
+            access_token: self.access_token,
+            tls_acceptor: match self.tls {
+                Some(tls) => Some(tls.build()?),
+                None => None,
+            },
+            heartbeat_watchdog: self.heartbeat_watchdog,
+            outbound: self.outbound,
+            dispatch: self.dispatch,
+            hooks: self.hooks,
+            recorder: match self.record_traffic {
+                Some(path) => Some(Arc::new(TrafficRecorder::open(path)?)),
+                None => None,
+            },
+            metrics: self.metrics,
             processors: Arc::new(self.processors),
+            middlewares: Arc::new(self.middlewares),
+            error_policy: self.error_policy,
+            manager: self.manager.unwrap_or_default(),
+            split_bots: DashMap::new(),
+            events: tokio::sync::broadcast::channel(64).0,
+            shutdown_tx: Mutex::new(Some(shutdown_tx)),
+            shutdown_rx: Mutex::new(Some(shutdown_rx)),
         }))
     }
 }
+
+/// 非 unix 平台不支持 unix socket, 在 build 时返回错误而不是忽略
+pub(crate) fn unix_socket_unsupported(path: &std::path::Path) -> Error {
+    Error::ParamsError(format!(
+        "unix socket {} is not supported on this platform",
+        path.display()
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct NullWriter;
+
+    #[async_trait]
+    impl WsWriter for NullWriter {
+        async fn send_raw(&mut self, _msg: String) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_not_connected() {
+        let bot_ctx = BotContextBuilder::new().build().unwrap();
+        let result = bot_ctx.websocket_send("get_status", json!({})).await;
+        assert!(matches!(result, Err(Error::NotConnected(_))));
+        assert!(bot_ctx.echo_notifer.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_connection_lost() {
+        let bot_ctx = BotContextBuilder::new().build().unwrap();
+        let connection = BotConnection::new(Box::new(NullWriter));
+        let key = connection.key;
+        bot_ctx.set_connection(connection).await;
+        let echo = bot_ctx
+            .websocket_send("get_status", json!({}))
+            .await
+            .unwrap();
+        let waiting = tokio::spawn(echo.response(Duration::from_secs(10)));
+        bot_ctx.clear_connection(key).await;
+        let result = tokio::time::timeout(Duration::from_secs(1), waiting)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(matches!(result, Err(Error::ConnectionLost(_))));
+    }
+
+    #[tokio::test]
+    async fn test_http_writer() {
+        use hyper::server::conn::http1;
+        use hyper::service::service_fn;
+        use hyper_util::rt::TokioIo;
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (sender, mut requests) = tokio::sync::mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let service = service_fn(move |request: hyper::Request<hyper::body::Incoming>| {
+                let sender = sender.clone();
+                async move {
+                    let path = request.uri().path().to_string();
+                    let authorization = request
+                        .headers()
+                        .get(hyper::header::AUTHORIZATION)
+                        .map(|value| value.to_str().unwrap().to_string());
+                    let body = request.into_body().collect().await.unwrap().to_bytes();
+                    let params: serde_json::Value = serde_json::from_slice(&body).unwrap();
+                    sender.send((path, authorization, params)).unwrap();
+                    // http api 的响应不带 echo
+                    let response = json!({"status": "ok", "retcode": 0, "data": {"message_id": 7}});
+                    Ok::<_, std::convert::Infallible>(hyper::Response::new(Full::new(Bytes::from(
+                        response.to_string(),
+                    ))))
+                }
+            });
+            let _ = http1::Builder::new()
+                .serve_connection(TokioIo::new(stream), service)
+                .await;
+        });
+
+        let bot_ctx = BotContextBuilder::new()
+            .http_api_url(format!("http://{}/", addr))
+            .access_token("secret")
+            .build()
+            .unwrap();
+        let response = bot_ctx
+            .websocket_send("send_group_msg", json!({"group_id": 1, "message": "hello"}))
+            .await
+            .unwrap();
+        let echo = response.0.clone();
+        let response = response.response(Duration::from_secs(5)).await.unwrap();
+        assert_eq!(response.echo, echo);
+        assert_eq!(response.data["message_id"], 7);
+
+        let (path, authorization, params) = requests.recv().await.unwrap();
+        assert_eq!(path, "/send_group_msg");
+        assert_eq!(authorization.as_deref(), Some("Bearer secret"));
+        assert_eq!(params["group_id"], 1);
+        assert!(bot_ctx.echo_notifer.is_empty());
+    }
+
+    async fn wait_connection(bot_ctx: &BotContext, connected: bool) {
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while bot_ctx.connection.lock().await.is_some() != connected {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_offline_flush_after_reconnect() {
+        let mock = crate::testing::MockOneBot::start().await.unwrap();
+        let bot_ctx = BotContextBuilder::new()
+            .url(mock.url())
+            .reconnect_policy(ReconnectPolicy::fixed(Duration::from_millis(300)))
+            .offline_buffer(OfflineBuffer::new())
+            .build()
+            .unwrap();
+        tokio::spawn(crate::connection::loop_client(bot_ctx.clone()));
+        wait_connection(&bot_ctx, true).await;
+
+        mock.disconnect();
+        wait_connection(&bot_ctx, false).await;
+        let response = bot_ctx.send_group_message(1, "offline").await.unwrap();
+        assert_eq!(bot_ctx.offline_queue_len(), 1);
+
+        // 重连后发送, 原来的响应正常完成
+        mock.expect_action("send_group_msg")
+            .with_param("group_id", 1)
+            .with_text("offline")
+            .await;
+        response.wait_response().await.unwrap();
+        assert_eq!(bot_ctx.offline_queue_len(), 0);
+        bot_ctx.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_queued_action_expires_offline() {
+        let bot_ctx = BotContextBuilder::new()
+            .outbound(OutboundPolicy::new().global(crate::outbound::RateLimit::per_minute(1)))
+            .offline_buffer(OfflineBuffer::new().ttl(Duration::from_millis(50)))
+            .build()
+            .unwrap();
+        let connection = BotConnection::new(Box::new(NullWriter));
+        let key = connection.key;
+        bot_ctx.set_connection(connection).await;
+        let _sent = bot_ctx.send_group_message(1, "a").await.unwrap();
+        // 等待令牌, 断线时仍在出站队列中
+        let queued = bot_ctx.send_group_message(1, "b").await.unwrap();
+        bot_ctx.clear_connection(key).await;
+        assert!(bot_ctx.offline_queue_len() > 0);
+
+        // 不再重连时, 超过离线缓冲的 ttl 后结束等待
+        let result = tokio::time::timeout(Duration::from_secs(1), queued.wait_response())
+            .await
+            .unwrap();
+        assert!(matches!(result, Err(Error::ConnectionLost(_))));
+        assert_eq!(bot_ctx.offline_queue_len(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_queued_action_timeout() {
+        let bot_ctx = BotContextBuilder::new()
+            .outbound(OutboundPolicy::new().global(crate::outbound::RateLimit::per_minute(1)))
+            .build()
+            .unwrap();
+        bot_ctx
+            .set_connection(BotConnection::new(Box::new(NullWriter)))
+            .await;
+        let _sent = bot_ctx.send_group_message(1, "a").await.unwrap();
+        let queued = bot_ctx.send_group_message(1, "b").await.unwrap();
+        // 令牌一分钟后才可用, 排队等待同样受 timeout 限制
+        let result = tokio::time::timeout(
+            Duration::from_secs(1),
+            queued.wait_response_with_timeout(Duration::from_millis(50)),
+        )
+        .await
+        .unwrap();
+        assert!(matches!(result, Err(Error::TimeoutError(_, _))));
+        assert_eq!(bot_ctx.outbound_queue_depth(), 1);
+    }
+
+    #[derive(Debug)]
+    struct Slow {
+        processed: Arc<AtomicUsize>,
+        started: Arc<tokio::sync::Notify>,
+    }
+
+    #[async_trait]
+    impl crate::process::PostProcessor for Slow {
+        fn id(&self) -> &'static str {
+            "slow"
+        }
+
+        async fn process_post(
+            &self,
+            _bot_ctx: Arc<BotContext>,
+            _post: &Post,
+        ) -> anyhow::Result<bool> {
+            self.started.notify_one();
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            self.processed.fetch_add(1, Ordering::SeqCst);
+            Ok(true)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_graceful_shutdown() {
+        let processed = Arc::new(AtomicUsize::new(0));
+        let started = Arc::new(tokio::sync::Notify::new());
+        let bot_ctx = BotContextBuilder::new()
+            .add_processor(Processor::Post(Box::new(Slow {
+                processed: processed.clone(),
+                started: started.clone(),
+            })))
+            .build()
+            .unwrap();
+        let notice = json!({
+            "post_type": "notice", "notice_type": "friend_add",
+            "time": 0, "self_id": 1, "user_id": 2,
+        })
+        .to_string();
+        let running = {
+            let bot_ctx = bot_ctx.clone();
+            let notice = notice.clone();
+            tokio::spawn(async move { bot_ctx.handle_text(bot_ctx.clone(), &notice).await })
+        };
+        started.notified().await;
+        assert_eq!(bot_ctx.in_flight(), 1);
+
+        bot_ctx
+            .graceful_shutdown(Duration::from_secs(5))
+            .await
+            .unwrap();
+        assert_eq!(processed.load(Ordering::SeqCst), 1);
+        assert_eq!(bot_ctx.in_flight(), 0);
+        assert!(bot_ctx.is_shutdown());
+        running.await.unwrap();
+
+        // 关闭后的事件不再交给处理器
+        bot_ctx.handle_text(bot_ctx.clone(), &notice).await;
+        assert_eq!(processed.load(Ordering::SeqCst), 1);
+    }
+
+    struct CountingWriter(Arc<AtomicUsize>);
+
+    #[async_trait]
+    impl WsWriter for CountingWriter {
+        async fn send_raw(&mut self, _msg: String) -> Result<()> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_graceful_shutdown_drains_outbound() {
+        let written = Arc::new(AtomicUsize::new(0));
+        let bot_ctx = BotContextBuilder::new()
+            .outbound(OutboundPolicy::new().global(crate::outbound::RateLimit::per_second(10)))
+            .build()
+            .unwrap();
+        bot_ctx
+            .set_connection(BotConnection::new(Box::new(CountingWriter(written.clone()))))
+            .await;
+        // 不等待响应的 action 只留在出站队列中
+        for text in ["a", "b", "c"] {
+            drop(bot_ctx.send_group_message(1, text).await.unwrap());
+        }
+        assert!(bot_ctx.outbound_queue_depth() > 0);
+
+        bot_ctx
+            .graceful_shutdown(Duration::from_secs(5))
+            .await
+            .unwrap();
+        assert_eq!(bot_ctx.outbound_queue_depth(), 0);
+        assert_eq!(written.load(Ordering::SeqCst), 3);
+    }
+}
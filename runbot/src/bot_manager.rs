@@ -0,0 +1,87 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::bot_context::BotContext;
+use dashmap::DashMap;
+
+/// 多账号管理, 按 self_id 查找 bot
+///
+/// bot 的 id 来自反向ws 的 `X-Self-ID` 请求头、lifecycle 元事件或连接后的 `get_login_info`,
+/// 在 id 确定之前 `bot(self_id)` 无法找到该 bot
+#[derive(Debug, Default)]
+pub struct BotManager {
+    // key 为 Arc 指针地址, value 为 (加入顺序, bot)
+    bots: DashMap<usize, (u64, Arc<BotContext>)>,
+    sequence: AtomicU64,
+}
+
+impl BotManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&self, bot_ctx: Arc<BotContext>) {
+        let sequence = self.sequence.fetch_add(1, Ordering::Relaxed);
+        self.bots
+            .insert(Arc::as_ptr(&bot_ctx) as usize, (sequence, bot_ctx));
+    }
+
+    pub fn remove(&self, bot_ctx: &Arc<BotContext>) -> Option<Arc<BotContext>> {
+        self.bots
+            .remove(&(Arc::as_ptr(bot_ctx) as usize))
+            .map(|(_, (_, bot_ctx))| bot_ctx)
+    }
+
+    /// 根据账号查找 bot, 同一账号存在多个连接时返回最后加入的
+    pub fn bot(&self, self_id: i64) -> Option<Arc<BotContext>> {
+        self.bots
+            .iter()
+            .filter(|e| e.value().1.id() == self_id)
+            .max_by_key(|e| e.value().0)
+            .map(|e| e.value().1.clone())
+    }
+
+    pub fn bots(&self) -> Vec<Arc<BotContext>> {
+        self.bots.iter().map(|e| e.value().1.clone()).collect()
+    }
+
+    /// 所有已知账号, 尚未确定 id 的 bot 不包含在内
+    pub fn self_ids(&self) -> Vec<i64> {
+        let mut self_ids: Vec<i64> = self
+            .bots
+            .iter()
+            .map(|e| e.value().1.id())
+            .filter(|id| *id != 0)
+            .collect();
+        self_ids.sort();
+        self_ids.dedup();
+        self_ids
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bot_context::BotContextBuilder;
+
+    #[tokio::test]
+    async fn test_bot_lookup() {
+        let manager = BotManager::new();
+        let bot_a = BotContextBuilder::new().build().unwrap();
+        let bot_b = BotContextBuilder::new().build().unwrap();
+        manager.add(bot_a.clone());
+        manager.add(bot_b.clone());
+        assert!(manager.bot(10001).is_none());
+        assert!(manager.self_ids().is_empty());
+
+        bot_a.set_id(10001);
+        bot_b.set_id(10002);
+        assert!(Arc::ptr_eq(&manager.bot(10001).unwrap(), &bot_a));
+        assert!(Arc::ptr_eq(&manager.bot(10002).unwrap(), &bot_b));
+        assert_eq!(manager.self_ids(), vec![10001, 10002]);
+
+        manager.remove(&bot_a);
+        assert!(manager.bot(10001).is_none());
+        assert_eq!(manager.bots().len(), 1);
+    }
+}
@@ -1,17 +1,61 @@
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::path::PathBuf;
 use std::sync::Arc;
 
 use crate::bot_context::*;
 use crate::error::{Error, Result};
-use dashmap::DashMap;
+use crate::heartbeat::watch_heartbeat;
+use crate::reconnect::ReconnectAttempt;
+use crate::traffic::Direction;
+use bytes::Bytes;
 use futures_util::StreamExt;
+use hmac::{Hmac, Mac};
+use http_body_util::{BodyExt, Full};
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::{Method, StatusCode};
+use hyper_util::rt::TokioIo;
+use sha1::Sha1;
+use subtle::ConstantTimeEq;
 use tokio::io::{AsyncRead, AsyncWrite};
-use tokio::net::TcpListener;
-use tokio::sync::Mutex;
+use tokio::net::{TcpListener, TcpStream};
 use tokio::time::{Duration, sleep};
-use tokio_tungstenite::{WebSocketStream, accept_async, connect_async};
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::handshake::client::Request as ClientRequest;
+use tokio_tungstenite::tungstenite::handshake::server::{ErrorResponse, Request, Response};
+use tokio_tungstenite::tungstenite::http::{HeaderMap, HeaderValue, StatusCode as WsStatusCode};
+use tokio_tungstenite::tungstenite::protocol::Message as WsMessage;
+use tokio_tungstenite::{
+    Connector, MaybeTlsStream, WebSocketStream, accept_hdr_async, client_async,
+    client_async_tls_with_config, connect_async_tls_with_config,
+};
 use futures_util::future::Either;
 
-async fn loop_bot<S>(bot_ctx: Arc<BotContext>, ws_stream: WebSocketStream<S>)
+/// 反向ws 连接的角色, 来自 `X-Client-Role` 请求头或连接路径
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClientRole {
+    /// 同时收发事件与 API
+    Universal,
+    /// 仅用于调用 API
+    Api,
+    /// 仅用于上报事件
+    Event,
+}
+
+impl ClientRole {
+    /// 是否可以通过该连接发送 action
+    pub fn is_api(&self) -> bool {
+        matches!(self, ClientRole::Universal | ClientRole::Api)
+    }
+
+    /// 是否通过该连接接收事件
+    pub fn is_event(&self) -> bool {
+        matches!(self, ClientRole::Universal | ClientRole::Event)
+    }
+}
+
+async fn loop_bot<S>(bot_ctx: Arc<BotContext>, ws_stream: WebSocketStream<S>, role: ClientRole)
 where
     S: AsyncRead + AsyncWrite + Sync + Send + Unpin + 'static,
 {
@@ -28,13 +72,51 @@ where
     };
 
     let (ws_sink, mut split_stream) = ws_stream.split();
-    let connection = BotConnection {
-        sender: Box::new(ws_sink),
+    // 仅 Event 角色的连接不发送 action, 不设置为 bot 的连接
+    let connection_key = if role.is_api() {
+        let connection = BotConnection::new(Box::new(ws_sink));
+        let key = connection.key;
+        bot_ctx.set_connection(connection).await;
+        bot_ctx.hooks.connected(&bot_ctx);
+        Some(key)
+    } else {
+        None
     };
-    bot_ctx.set_connection(connection).await;
-    
+
+    // 没有通过请求头或事件得知账号时, 主动查询登录信息
+    if connection_key.is_some() && bot_ctx.id() == 0 {
+        let bot_ctx = bot_ctx.clone();
+        tokio::spawn(async move {
+            match bot_ctx.get_login_info().await {
+                Ok(login_info) => bot_ctx.set_id(login_info.user_id),
+                Err(err) => tracing::warn!("get_login_info error: {:?}", err),
+            }
+        });
+    }
+
+    // 心跳只通过接收事件的连接上报
+    let watchdog_fut = match bot_ctx.heartbeat_watchdog.clone() {
+        Some(watchdog) if role.is_event() => {
+            Either::Left(Box::pin(watch_heartbeat(bot_ctx.clone(), watchdog)))
+        }
+        _ => Either::Right(futures_util::future::pending()),
+    };
+    // 收到 pong 时通知 keepalive
+    let (pong_tx, pong_rx) = tokio::sync::watch::channel(());
+    let keepalive_fut = match (bot_ctx.ping_interval, connection_key) {
+        (Some(ping_interval), Some(key)) => Either::Left(Box::pin(keepalive(
+            bot_ctx.clone(),
+            key,
+            ping_interval,
+            bot_ctx.pong_timeout,
+            pong_rx,
+        ))),
+        _ => Either::Right(futures_util::future::pending()),
+    };
+    let mut health_fut = futures_util::future::select(watchdog_fut, keepalive_fut);
+
     loop {
-        // 使用 select! 来同时等待消息和 shutdown 信号
+        // 使用 select! 来同时等待消息, 心跳看门狗, keepalive 和 shutdown 信号
         let shutdown_fut = async {
             if let Some(ref mut rx) = shutdown_rx {
                 rx.changed().await.ok();
@@ -46,18 +128,43 @@ where
 
         let msg_fut = split_stream.next();
 
-        match futures_util::future::select(
-            Box::pin(shutdown_fut),
-            Box::pin(msg_fut),
-        ).await {
-            Either::Left((shutdown, _)) => {
+        let stop_fut = futures_util::future::select(Box::pin(shutdown_fut), &mut health_fut);
+
+        match futures_util::future::select(stop_fut, Box::pin(msg_fut)).await {
+            Either::Left((Either::Left((shutdown, _)), _)) => {
                 if shutdown {
                     tracing::info!("Shutdown signal received, exiting loop_bot");
                     break;
                 }
             }
+            Either::Left((Either::Right((Either::Left((unhealthy, _)), _)), _)) => {
+                if let Some(watchdog) = &bot_ctx.heartbeat_watchdog {
+                    watchdog.notify(&bot_ctx, &unhealthy);
+                }
+                break;
+            }
+            Either::Left((Either::Right((Either::Right((err, _)), _)), _)) => {
+                tracing::warn!("WS keepalive failed: {:?}", err);
+                break;
+            }
             Either::Right((msg_option, _)) => {
                 match msg_option {
+                    // ping 由 tungstenite 自动回复 pong
+                    Some(Ok(WsMessage::Ping(_))) => {}
+                    Some(Ok(WsMessage::Pong(_))) => {
+                        pong_tx.send_replace(());
+                    }
+                    Some(Ok(WsMessage::Close(frame))) => {
+                        tracing::info!("WebSocket close received: {:?}", frame);
+                        // 继续读取以发出 close 回复, 随后流结束
+                        let drain = async { while let Some(Ok(_)) = split_stream.next().await {} };
+                        let _ = tokio::time::timeout(Duration::from_secs(1), drain).await;
+                        break;
+                    }
+                    // 启用 dispatch 时在读取循环中入队, 保持事件的到达顺序
+                    Some(Ok(m)) if bot_ctx.dispatcher.is_some() => {
+                        bot_ctx.handle_receive(bot_ctx.clone(), &m).await;
+                    }
                     Some(Ok(m)) => {
                         let bot_ctx = bot_ctx.clone();
                         _ = tokio::spawn(async move { bot_ctx.handle_receive(bot_ctx.clone(), &m).await });
@@ -74,35 +181,202 @@ where
             }
         }
     }
-    bot_ctx.set_connection(None).await;
+    if let Some(key) = connection_key {
+        bot_ctx.clear_connection(key).await;
+        bot_ctx.hooks.disconnected(&bot_ctx);
+    }
+}
+
+/// 定时发送 ping, 超时未收到 pong 时返回错误
+async fn keepalive(
+    bot_ctx: Arc<BotContext>,
+    key: u64,
+    ping_interval: Duration,
+    pong_timeout: Duration,
+    mut pong_rx: tokio::sync::watch::Receiver<()>,
+) -> Error {
+    loop {
+        sleep(ping_interval).await;
+        pong_rx.mark_unchanged();
+        let sent = {
+            let mut connection_lock = bot_ctx.connection.lock().await;
+            match connection_lock.as_mut() {
+                Some(connection) if connection.key == key => connection.send_ping().await,
+                _ => Err(Error::ConnectionLost("connection replaced".to_string())),
+            }
+        };
+        if let Err(err) = sent {
+            return err;
+        }
+        if let Err(err) = tokio::time::timeout(pong_timeout, pong_rx.changed()).await {
+            return Error::TimeoutError("pong timeout".to_string(), err);
+        }
+    }
 }
 
 pub async fn loop_server(bot_server: Arc<BotServer>) -> Result<()> {
-    // 监听本地 9001 端口
-    let listener = TcpListener::bind(&bot_server.bind).await.unwrap();
-    println!("WebSocket server started on ws://{}", &bot_server.bind);
-    while let Ok((stream, _)) = listener.accept().await {
-        let processors = bot_server.processors.clone();
-        tokio::spawn(async move {
-            // 协议升级为 WebSocket
-            let ws_stream = accept_async(stream).await.unwrap();
-            let (shutdown_tx, shutdown_rx) = tokio::sync::watch::channel(false);
-            loop_bot(
-                Arc::new(BotContext {
-                    connection: Mutex::new(None),
-                    url: None,
-                    id: 0,
-                    processors,
-                    echo_notifer: Arc::new(DashMap::new()),
-                    shutdown_tx: Mutex::new(Some(shutdown_tx)),
-                    shutdown_rx: Mutex::new(Some(shutdown_rx)),
-                }),
-                ws_stream,
-            )
-            .await;
-        });
+    // 检查是否已经 shutdown
+    if bot_server.is_shutdown() {
+        return Err(Error::StateError(
+            "Bot server is already shutdown, cannot start loop_server".to_string(),
+        ));
+    }
+
+    // 获取 shutdown receiver
+    let mut shutdown_rx = {
+        let rx_lock = bot_server.shutdown_rx.lock().await;
+        rx_lock.as_ref().cloned()
+    };
+
+    #[cfg(unix)]
+    if let Some(path) = bot_server.unix_socket.clone() {
+        return loop_unix_server(bot_server, shutdown_rx, path).await;
+    }
+
+    let listener = TcpListener::bind(&bot_server.bind).await?;
+    let scheme = if bot_server.tls_acceptor.is_some() { "wss" } else { "ws" };
+    println!("WebSocket server started on {}://{}", scheme, &bot_server.bind);
+
+    loop {
+        let shutdown_fut = async {
+            if let Some(ref mut rx) = shutdown_rx {
+                rx.changed().await.ok();
+                *rx.borrow()
+            } else {
+                false
+            }
+        };
+
+        let accept_fut = listener.accept();
+
+        match futures_util::future::select(Box::pin(shutdown_fut), Box::pin(accept_fut)).await {
+            Either::Left((shutdown, _)) => {
+                if shutdown {
+                    tracing::info!("Shutdown signal received, exiting loop_server");
+                    return Ok(());
+                }
+            }
+            Either::Right((accept_result, _)) => match accept_result {
+                Ok((stream, addr)) => {
+                    tokio::spawn(accept_connection(bot_server.clone(), stream, addr.to_string()));
+                }
+                Err(e) => tracing::error!("WS accept error: {:?}", e),
+            },
+        }
+    }
+}
+
+/// 通过 unix socket 提供反向ws, 启动时清理残留的 socket 文件, 退出时删除
+#[cfg(unix)]
+async fn loop_unix_server(
+    bot_server: Arc<BotServer>,
+    mut shutdown_rx: Option<tokio::sync::watch::Receiver<bool>>,
+    path: PathBuf,
+) -> Result<()> {
+    use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+
+    if let Ok(metadata) = std::fs::symlink_metadata(&path) {
+        if !metadata.file_type().is_socket() {
+            return Err(Error::ParamsError(format!(
+                "{} exists and is not a socket",
+                path.display()
+            )));
+        }
+        std::fs::remove_file(&path)?;
+    }
+    let listener = tokio::net::UnixListener::bind(&path)?;
+    if let Some(mode) = bot_server.unix_socket_mode {
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(mode))?;
+    }
+    tracing::info!("WebSocket server started on unix:{}", path.display());
+
+    let result = loop {
+        let shutdown_fut = async {
+            if let Some(ref mut rx) = shutdown_rx {
+                rx.changed().await.ok();
+                *rx.borrow()
+            } else {
+                false
+            }
+        };
+
+        let accept_fut = listener.accept();
+
+        match futures_util::future::select(Box::pin(shutdown_fut), Box::pin(accept_fut)).await {
+            Either::Left((shutdown, _)) => {
+                if shutdown {
+                    tracing::info!("Shutdown signal received, exiting loop_server");
+                    break Ok(());
+                }
+            }
+            Either::Right((accept_result, _)) => match accept_result {
+                Ok((stream, _)) => {
+                    let peer = format!("unix:{}", path.display());
+                    tokio::spawn(accept_connection(bot_server.clone(), stream, peer));
+                }
+                Err(e) => tracing::error!("WS accept error: {:?}", e),
+            },
+        }
+    };
+    let _ = std::fs::remove_file(&path);
+    result
+}
+
+async fn accept_connection<S>(bot_server: Arc<BotServer>, stream: S, addr: String)
+where
+    S: AsyncRead + AsyncWrite + Sync + Send + Unpin + 'static,
+{
+    match bot_server.tls_acceptor.clone() {
+        Some(tls_acceptor) => match tls_acceptor.accept(stream).await {
+            Ok(tls_stream) => serve_connection(bot_server, tls_stream, addr).await,
+            Err(e) => tracing::warn!("WS {} tls handshake error: {:?}", addr, e),
+        },
+        None => serve_connection(bot_server, stream, addr).await,
     }
-    Ok(())
+}
+
+async fn serve_connection<S>(bot_server: Arc<BotServer>, stream: S, addr: String)
+where
+    S: AsyncRead + AsyncWrite + Sync + Send + Unpin + 'static,
+{
+    // 协议升级为 WebSocket, 握手时校验 access_token 并读取 X-Self-ID
+    let access_token = bot_server.access_token.clone();
+    let mut self_id = 0;
+    let mut role = ClientRole::Universal;
+    let callback = |request: &Request, response: Response| {
+        self_id = header_self_id(request.headers());
+        role = client_role(request);
+        match &access_token {
+            Some(access_token) => match check_access_token(request, access_token) {
+                Ok(()) => Ok(response),
+                Err(status) => {
+                    tracing::warn!("WS {} rejected: {}", addr, status);
+                    let mut error_response = ErrorResponse::new(Some(status.to_string()));
+                    *error_response.status_mut() = status;
+                    Err(error_response)
+                }
+            },
+            None => Ok(response),
+        }
+    };
+    let ws_stream = match accept_hdr_async(stream, callback).await {
+        Ok(ws_stream) => ws_stream,
+        // 被拒绝的握手已经在 callback 中记录
+        Err(tokio_tungstenite::tungstenite::Error::Http(_)) => return,
+        Err(e) => {
+            tracing::warn!("WS {} handshake error: {:?}", addr, e);
+            return;
+        }
+    };
+    // 握手期间 server 可能已经 shutdown
+    if bot_server.is_shutdown() {
+        return;
+    }
+    tracing::info!("WS {} Connected! self_id: {} role: {:?}", addr, self_id, role);
+    let bot_ctx = bot_server.attach(self_id, role);
+    loop_bot(bot_ctx.clone(), ws_stream, role).await;
+    bot_server.detach(&bot_ctx);
+    tracing::info!("WS {} Disconnected", addr);
 }
 
 pub async fn loop_client(bot_ctx: Arc<BotContext>) -> Result<()> {
@@ -113,13 +387,17 @@ pub async fn loop_client(bot_ctx: Arc<BotContext>) -> Result<()> {
         ));
     }
 
-    let url = bot_ctx
-        .url
-        .as_ref()
-        .ok_or(Error::ParamsError(
-            "url must be set for loop client".to_string(),
-        ))
-        .map(|e| e.clone())?;
+    // unix socket 连接时 url 只用于握手请求, 可以不设置
+    let url = match (&bot_ctx.url, &bot_ctx.unix_socket) {
+        (Some(url), _) => url.clone(),
+        (None, Some(_)) => "ws://localhost/".to_string(),
+        (None, None) => {
+            return Err(Error::ParamsError(
+                "url must be set for loop client".to_string(),
+            ));
+        }
+    };
+    let request = client_request(&url, bot_ctx.access_token.as_deref())?;
 
     // 获取 shutdown receiver
     let mut shutdown_rx = {
@@ -127,6 +405,9 @@ pub async fn loop_client(bot_ctx: Arc<BotContext>) -> Result<()> {
         rx_lock.as_ref().cloned()
     };
 
+    // 连续重连的次数, 连接成功后清零
+    let mut attempt: u32 = 0;
+
     loop {
         // 检查 shutdown 信号
         if bot_ctx.is_shutdown() {
@@ -134,6 +415,8 @@ pub async fn loop_client(bot_ctx: Arc<BotContext>) -> Result<()> {
             return Ok(());
         }
 
+        let mut last_error = None;
+
         // 使用 select! 来同时等待连接和 shutdown 信号
         let shutdown_fut = async {
             if let Some(ref mut rx) = shutdown_rx {
@@ -144,7 +427,7 @@ pub async fn loop_client(bot_ctx: Arc<BotContext>) -> Result<()> {
             }
         };
 
-        let connect_fut = connect_async(&url);
+        let connect_fut = connect_client(&bot_ctx, request.clone());
 
         match futures_util::future::select(
             Box::pin(shutdown_fut),
@@ -158,11 +441,23 @@ pub async fn loop_client(bot_ctx: Arc<BotContext>) -> Result<()> {
             }
             Either::Right((connect_result, _)) => {
                 match connect_result {
-                    Ok((ws_stream, _)) => {
+                    Ok(client_stream) => {
                         tracing::info!("WS {} Connected!", &url);
-                        let _ = loop_bot(bot_ctx.clone(), ws_stream).await;
+                        attempt = 0;
+                        match client_stream {
+                            ClientStream::Tcp(ws_stream) => {
+                                loop_bot(bot_ctx.clone(), *ws_stream, ClientRole::Universal).await
+                            }
+                            #[cfg(unix)]
+                            ClientStream::Unix(ws_stream) => {
+                                loop_bot(bot_ctx.clone(), *ws_stream, ClientRole::Universal).await
+                            }
+                        }
+                    }
+                    Err(e) => {
+                        tracing::error!("WS {} connect error: {:?}", &url, e);
+                        last_error = Some(e);
                     }
-                    Err(e) => tracing::error!("WS {} connect error: {:?}", &url, e),
                 }
             }
         }
@@ -173,6 +468,24 @@ pub async fn loop_client(bot_ctx: Arc<BotContext>) -> Result<()> {
             return Ok(());
         }
 
+        attempt = attempt.saturating_add(1);
+        let exhausted = bot_ctx.reconnect_policy.exhausted(attempt);
+        let delay = bot_ctx.reconnect_policy.delay(attempt);
+        if let Some(observer) = &bot_ctx.reconnect_observer {
+            (observer.0)(&ReconnectAttempt {
+                attempt,
+                error: last_error.as_ref(),
+                delay: (!exhausted).then_some(delay),
+            });
+        }
+        if exhausted {
+            tracing::error!("WS {} reconnect attempts exhausted, exiting loop_client", &url);
+            return Err(Error::StateError(format!(
+                "reconnect attempts exhausted after {} attempts",
+                attempt - 1
+            )));
+        }
+
         // 使用 select! 来同时等待 sleep 和 shutdown 信号
         let shutdown_fut = async {
             if let Some(ref mut rx) = shutdown_rx {
@@ -183,7 +496,7 @@ pub async fn loop_client(bot_ctx: Arc<BotContext>) -> Result<()> {
             }
         };
 
-        let sleep_fut = sleep(Duration::from_secs(15));
+        let sleep_fut = sleep(delay);
 
         match futures_util::future::select(
             Box::pin(shutdown_fut),
@@ -196,8 +509,584 @@ pub async fn loop_client(bot_ctx: Arc<BotContext>) -> Result<()> {
                 }
             }
             Either::Right(_) => {
-                tracing::info!("WS {} reconnecting after 15s...", &url);
+                tracing::info!("WS {} reconnecting after {:?} (attempt {})...", &url, delay, attempt);
             }
         }
     }
 }
+
+/// loop_client 建立的 ws 连接
+enum ClientStream {
+    Tcp(Box<WebSocketStream<MaybeTlsStream<TcpStream>>>),
+    #[cfg(unix)]
+    Unix(Box<WebSocketStream<tokio::net::UnixStream>>),
+}
+
+/// 等待 SIGINT 或 SIGTERM (非 unix 平台为 ctrl-c)
+pub async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{SignalKind, signal};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                futures_util::future::select(
+                    Box::pin(tokio::signal::ctrl_c()),
+                    Box::pin(terminate.recv()),
+                )
+                .await;
+                return;
+            }
+            Err(e) => tracing::warn!("Listen SIGTERM error: {:?}", e),
+        }
+    }
+    if let Err(e) = tokio::signal::ctrl_c().await {
+        tracing::warn!("Listen ctrl-c error: {:?}", e);
+        futures_util::future::pending::<()>().await;
+    }
+}
+
+/// 运行 `loop_client`, 收到 SIGINT / SIGTERM 后优雅关闭, 适合在容器中部署
+///
+/// 等待处理器与 action 响应期间连接保持, 最多等待 `drain_timeout`
+pub async fn run_until_signal(bot_ctx: Arc<BotContext>, drain_timeout: Duration) -> Result<()> {
+    let client = Box::pin(loop_client(bot_ctx.clone()));
+    match futures_util::future::select(client, Box::pin(shutdown_signal())).await {
+        Either::Left((result, _)) => result,
+        Either::Right((_, client)) => {
+            tracing::info!("Shutdown signal received, draining");
+            let (result, shutdown_result) = futures_util::future::join(
+                client,
+                bot_ctx.graceful_shutdown(drain_timeout),
+            )
+            .await;
+            shutdown_result?;
+            result
+        }
+    }
+}
+
+/// 运行 `loop_server`, 收到 SIGINT / SIGTERM 后停止监听并优雅关闭所有 bot
+pub async fn run_server_until_signal(
+    bot_server: Arc<BotServer>,
+    drain_timeout: Duration,
+) -> Result<()> {
+    let server = Box::pin(loop_server(bot_server.clone()));
+    match futures_util::future::select(server, Box::pin(shutdown_signal())).await {
+        Either::Left((result, _)) => result,
+        Either::Right((_, server)) => {
+            tracing::info!("Shutdown signal received, draining");
+            let (result, shutdown_result) = futures_util::future::join(
+                server,
+                bot_server.graceful_shutdown(drain_timeout),
+            )
+            .await;
+            shutdown_result?;
+            result
+        }
+    }
+}
+
+async fn connect_client(bot_ctx: &BotContext, request: ClientRequest) -> Result<ClientStream> {
+    if let Some(path) = &bot_ctx.unix_socket {
+        #[cfg(unix)]
+        {
+            let stream = tokio::net::UnixStream::connect(path).await?;
+            let (ws_stream, _) = client_async(request, stream).await?;
+            return Ok(ClientStream::Unix(Box::new(ws_stream)));
+        }
+        #[cfg(not(unix))]
+        return Err(unix_socket_unsupported(path));
+    }
+    let connector = bot_ctx.tls_config.clone().map(Connector::Rustls);
+    let (ws_stream, _) = match &bot_ctx.proxy {
+        Some(proxy) => {
+            let uri = request.uri();
+            let host = uri
+                .host()
+                .ok_or(Error::ParamsError(format!("url has no host: {}", uri)))?
+                .trim_start_matches('[')
+                .trim_end_matches(']')
+                .to_string();
+            let port = uri.port_u16().unwrap_or(match uri.scheme_str() {
+                Some("wss") => 443,
+                _ => 80,
+            });
+            let stream = proxy.connect(&host, port).await?;
+            client_async_tls_with_config(request, stream, None, connector).await?
+        }
+        None => connect_async_tls_with_config(request, None, false, connector).await?,
+    };
+    Ok(ClientStream::Tcp(Box::new(ws_stream)))
+}
+
+/// 生成 ws 握手请求, 携带 access_token 时附加 `Authorization: Bearer`
+fn client_request(url: &str, access_token: Option<&str>) -> Result<ClientRequest> {
+    let mut request = url.into_client_request()?;
+    if let Some(access_token) = access_token {
+        let value = HeaderValue::from_str(&format!("Bearer {}", access_token))
+            .map_err(|e| Error::ParamsError(format!("invalid access_token: {}", e)))?;
+        request.headers_mut().insert("Authorization", value);
+    }
+    Ok(request)
+}
+
+/// 读取 onebot 实现携带的 `X-Self-ID` 请求头, 不存在时为 0
+fn header_self_id(headers: &HeaderMap) -> i64 {
+    headers
+        .get("X-Self-ID")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse().ok())
+        .unwrap_or(0)
+}
+
+/// 读取 `X-Client-Role` 请求头, 不存在时根据路径 `/api` `/event` 判断
+fn client_role(request: &Request) -> ClientRole {
+    let header = request
+        .headers()
+        .get("X-Client-Role")
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.trim().to_ascii_lowercase());
+    match header.as_deref() {
+        Some("universal") => ClientRole::Universal,
+        Some("api") => ClientRole::Api,
+        Some("event") => ClientRole::Event,
+        _ => {
+            let path = request.uri().path().trim_end_matches('/');
+            if path.ends_with("/api") {
+                ClientRole::Api
+            } else if path.ends_with("/event") {
+                ClientRole::Event
+            } else {
+                ClientRole::Universal
+            }
+        }
+    }
+}
+
+/// 校验握手请求中的 access_token, 支持 `Authorization: Bearer / Token` 以及 `?access_token=`
+///
+/// 未携带 (或 Authorization 没有 scheme) 时返回 401, 不匹配时返回 403
+fn check_access_token(
+    request: &Request,
+    access_token: &str,
+) -> std::result::Result<(), WsStatusCode> {
+    let from_header = request
+        .headers()
+        .get("Authorization")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| {
+            let (scheme, token) = value.split_once(' ')?;
+            (scheme.eq_ignore_ascii_case("Bearer") || scheme.eq_ignore_ascii_case("Token"))
+                .then(|| token.trim().to_string())
+        });
+    let from_query = request.uri().query().and_then(|query| {
+        query
+            .split('&')
+            .find_map(|pair| pair.strip_prefix("access_token="))
+            .map(percent_decode)
+    });
+    match from_header.or(from_query) {
+        None => Err(WsStatusCode::UNAUTHORIZED),
+        // 逐字节比较耗时与 token 内容无关
+        Some(token) if bool::from(token.as_bytes().ct_eq(access_token.as_bytes())) => Ok(()),
+        Some(_) => Err(WsStatusCode::FORBIDDEN),
+    }
+}
+
+/// 解码查询参数中的 `%XX`, 无效的转义保持原样
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%'
+            && let Some(hex) = bytes.get(i + 1..i + 3)
+            && hex.iter().all(u8::is_ascii_hexdigit)
+        {
+            let hex = std::str::from_utf8(hex).unwrap();
+            decoded.push(u8::from_str_radix(hex, 16).unwrap());
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// 监听 onebot http post 事件上报, 收到的事件与 ws 一样交给处理器链
+pub async fn loop_http_post(bot_ctx: Arc<BotContext>) -> Result<()> {
+    // 检查是否已经 shutdown
+    if bot_ctx.is_shutdown() {
+        return Err(Error::StateError(
+            "Bot is already shutdown, cannot start loop_http_post".to_string(),
+        ));
+    }
+
+    let bind = bot_ctx
+        .http_post_bind
+        .as_ref()
+        .ok_or(Error::ParamsError(
+            "http_post_bind must be set for loop http post".to_string(),
+        ))
+        .cloned()?;
+
+    // 获取 shutdown receiver
+    let mut shutdown_rx = {
+        let rx_lock = bot_ctx.shutdown_rx.lock().await;
+        rx_lock.as_ref().cloned()
+    };
+
+    let listener = TcpListener::bind(&bind).await?;
+    tracing::info!("HTTP post server started on http://{}", &bind);
+
+    loop {
+        let shutdown_fut = async {
+            if let Some(ref mut rx) = shutdown_rx {
+                rx.changed().await.ok();
+                *rx.borrow()
+            } else {
+                false
+            }
+        };
+
+        let accept_fut = listener.accept();
+
+        match futures_util::future::select(Box::pin(shutdown_fut), Box::pin(accept_fut)).await {
+            Either::Left((shutdown, _)) => {
+                if shutdown {
+                    tracing::info!("Shutdown signal received, exiting loop_http_post");
+                    return Ok(());
+                }
+            }
+            Either::Right((accept_result, _)) => match accept_result {
+                Ok((stream, addr)) => {
+                    let bot_ctx = bot_ctx.clone();
+                    tokio::spawn(async move {
+                        let service = service_fn(move |request| {
+                            handle_http_post(bot_ctx.clone(), addr, request)
+                        });
+                        if let Err(err) = http1::Builder::new()
+                            .serve_connection(TokioIo::new(stream), service)
+                            .await
+                        {
+                            tracing::error!("HTTP post connection {} error: {:?}", addr, err);
+                        }
+                    });
+                }
+                Err(e) => tracing::error!("HTTP post accept error: {:?}", e),
+            },
+        }
+    }
+}
+
+async fn handle_http_post(
+    bot_ctx: Arc<BotContext>,
+    addr: SocketAddr,
+    request: hyper::Request<hyper::body::Incoming>,
+) -> std::result::Result<hyper::Response<Full<Bytes>>, Infallible> {
+    if request.method() != Method::POST {
+        return Ok(http_status(StatusCode::METHOD_NOT_ALLOWED));
+    }
+    let self_id = header_self_id(request.headers());
+    let signature = request
+        .headers()
+        .get("X-Signature")
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.to_string());
+    let body = match request.into_body().collect().await {
+        Ok(body) => body.to_bytes(),
+        Err(err) => {
+            tracing::error!("HTTP post read body error: {:?}", err);
+            return Ok(http_status(StatusCode::BAD_REQUEST));
+        }
+    };
+    // 在解析之前校验签名, 未签名或签名错误的事件直接丢弃
+    if let Some(secret) = &bot_ctx.secret
+        && let Err(err) = verify_signature(secret, signature.as_deref(), &body)
+    {
+        tracing::warn!("HTTP post {} dropped: {}", addr, err);
+        return Ok(http_status(StatusCode::UNAUTHORIZED));
+    }
+    bot_ctx.set_id(self_id);
+    let text = match String::from_utf8(body.to_vec()) {
+        Ok(text) => text,
+        Err(err) => {
+            tracing::error!("HTTP post body is not utf-8: {:?}", err);
+            return Ok(http_status(StatusCode::BAD_REQUEST));
+        }
+    };
+    tracing::debug!("HTTP received: {}", text);
+    bot_ctx.record(Direction::In, &text);
+    // 不使用快速操作, 立即返回 204, 处理器在后台运行
+    if bot_ctx.dispatcher.is_some() {
+        bot_ctx.handle_text(bot_ctx.clone(), &text).await;
+    } else {
+        tokio::spawn(async move { bot_ctx.handle_text(bot_ctx.clone(), &text).await });
+    }
+    Ok(http_status(StatusCode::NO_CONTENT))
+}
+
+/// 校验 `X-Signature: sha1=<hex>`, 签名为使用 secret 对原始请求体计算的 HMAC-SHA1
+fn verify_signature(secret: &str, signature: Option<&str>, body: &[u8]) -> Result<()> {
+    let signature = signature
+        .ok_or(Error::SignatureError("X-Signature not found".to_string()))?;
+    let signature = signature
+        .strip_prefix("sha1=")
+        .ok_or(Error::SignatureError(format!("unknown signature: {}", signature)))?;
+    let signature = hex::decode(signature)
+        .map_err(|e| Error::SignatureError(format!("signature not hex: {}", e)))?;
+    let mut mac = Hmac::<Sha1>::new_from_slice(secret.as_bytes())
+        .map_err(|e| Error::SignatureError(e.to_string()))?;
+    mac.update(body);
+    mac.verify_slice(&signature)
+        .map_err(|_| Error::SignatureError("signature mismatch".to_string()))
+}
+
+fn http_status(status: StatusCode) -> hyper::Response<Full<Bytes>> {
+    let mut response = hyper::Response::new(Full::new(Bytes::new()));
+    *response.status_mut() = status;
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(uri: &str, authorization: Option<&str>) -> Request {
+        let mut builder = Request::builder().uri(uri);
+        if let Some(authorization) = authorization {
+            builder = builder.header("Authorization", authorization);
+        }
+        builder.body(()).unwrap()
+    }
+
+    #[test]
+    fn test_check_access_token() {
+        let token = "secret";
+        assert!(check_access_token(&request("/", Some("Bearer secret")), token).is_ok());
+        assert!(check_access_token(&request("/", Some("Token secret")), token).is_ok());
+        assert!(check_access_token(&request("/?access_token=secret", None), token).is_ok());
+        assert_eq!(
+            check_access_token(&request("/", None), token),
+            Err(WsStatusCode::UNAUTHORIZED)
+        );
+        assert_eq!(
+            check_access_token(&request("/", Some("Bearer other")), token),
+            Err(WsStatusCode::FORBIDDEN)
+        );
+        // 没有 scheme 的 Authorization 视为未携带
+        assert_eq!(
+            check_access_token(&request("/", Some("secret")), token),
+            Err(WsStatusCode::UNAUTHORIZED)
+        );
+        assert!(check_access_token(&request("/", Some("bearer secret")), token).is_ok());
+
+        // 查询参数中的 token 需要解码
+        let token = "a+b/c=&d";
+        assert!(
+            check_access_token(&request("/?access_token=a%2Bb%2Fc%3D%26d", None), token).is_ok()
+        );
+        assert_eq!(percent_decode("%E4%BD%A0%zz%4"), "你%zz%4");
+    }
+
+    #[test]
+    fn test_client_role() {
+        let role = |uri: &str, header: Option<&str>| {
+            let mut builder = Request::builder().uri(uri);
+            if let Some(header) = header {
+                builder = builder.header("X-Client-Role", header);
+            }
+            client_role(&builder.body(()).unwrap())
+        };
+        assert_eq!(role("/", None), ClientRole::Universal);
+        assert_eq!(role("/onebot/api/", None), ClientRole::Api);
+        assert_eq!(role("/onebot/event", None), ClientRole::Event);
+        assert_eq!(role("/", Some("API")), ClientRole::Api);
+        assert_eq!(role("/api", Some("Universal")), ClientRole::Universal);
+    }
+
+    #[test]
+    fn test_verify_signature() {
+        let body = br#"{"post_type":"meta_event"}"#;
+        let mut mac = Hmac::<Sha1>::new_from_slice(b"secret").unwrap();
+        mac.update(body);
+        let signature = format!("sha1={}", hex::encode(mac.finalize().into_bytes()));
+        assert!(verify_signature("secret", Some(&signature), body).is_ok());
+        assert!(matches!(
+            verify_signature("other", Some(&signature), body),
+            Err(Error::SignatureError(_))
+        ));
+        assert!(matches!(
+            verify_signature("secret", None, body),
+            Err(Error::SignatureError(_))
+        ));
+    }
+
+    #[test]
+    fn test_client_request_authorization() {
+        let request = client_request("ws://localhost:3001", Some("secret")).unwrap();
+        assert_eq!(
+            request.headers().get("Authorization").unwrap(),
+            "Bearer secret"
+        );
+        let request = client_request("ws://localhost:3001", None).unwrap();
+        assert!(request.headers().get("Authorization").is_none());
+    }
+
+    #[derive(Debug)]
+    struct Notices(tokio::sync::mpsc::UnboundedSender<i64>);
+
+    #[async_trait::async_trait]
+    impl crate::process::NoticeProcessor for Notices {
+        fn id(&self) -> &'static str {
+            "notices"
+        }
+
+        async fn process_notice(
+            &self,
+            _bot_ctx: Arc<BotContext>,
+            notice: &crate::event::Notice,
+        ) -> anyhow::Result<bool> {
+            let _ = self.0.send(notice.user_id().unwrap_or_default());
+            Ok(true)
+        }
+    }
+
+    fn free_addr() -> String {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.local_addr().unwrap().to_string()
+    }
+
+    fn friend_add(user_id: i64) -> String {
+        serde_json::json!({
+            "post_type": "notice", "notice_type": "friend_add",
+            "time": 0, "self_id": 1, "user_id": user_id,
+        })
+        .to_string()
+    }
+
+    async fn post_event(
+        addr: &str,
+        self_id: i64,
+        body: &str,
+        signature: Option<&str>,
+    ) -> StatusCode {
+        let client =
+            hyper_util::client::legacy::Client::builder(hyper_util::rt::TokioExecutor::new())
+                .build_http();
+        for _ in 0..100 {
+            let mut request = hyper::Request::post(format!("http://{}/", addr))
+                .header("X-Self-ID", self_id.to_string());
+            if let Some(signature) = signature {
+                request = request.header("X-Signature", signature);
+            }
+            let request = request
+                .body(Full::new(Bytes::from(body.to_string())))
+                .unwrap();
+            match client.request(request).await {
+                Ok(response) => return response.status(),
+                // 监听尚未开始
+                Err(_) => sleep(Duration::from_millis(10)).await,
+            }
+        }
+        panic!("http post server not started on {}", addr);
+    }
+
+    #[tokio::test]
+    async fn test_http_post() {
+        let bind = free_addr();
+        let (sender, mut notices) = tokio::sync::mpsc::unbounded_channel();
+        let bot_ctx = BotContextBuilder::new()
+            .http_post_bind(bind.clone())
+            .add_processor(crate::process::Processor::Notice(Box::new(Notices(sender))))
+            .build()
+            .unwrap();
+        tokio::spawn(loop_http_post(bot_ctx.clone()));
+
+        let status = post_event(&bind, 1, &friend_add(2), None).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let user_id = tokio::time::timeout(Duration::from_secs(1), notices.recv())
+            .await
+            .unwrap();
+        assert_eq!(user_id, Some(2));
+        assert_eq!(bot_ctx.id(), 1);
+        bot_ctx.shutdown().await.unwrap();
+    }
+
+    fn sign(secret: &str, body: &str) -> String {
+        let mut mac = Hmac::<Sha1>::new_from_slice(secret.as_bytes()).unwrap();
+        mac.update(body.as_bytes());
+        format!("sha1={}", hex::encode(mac.finalize().into_bytes()))
+    }
+
+    #[tokio::test]
+    async fn test_http_post_signature() {
+        let bind = free_addr();
+        let (sender, mut notices) = tokio::sync::mpsc::unbounded_channel();
+        let bot_ctx = BotContextBuilder::new()
+            .http_post_bind(bind.clone())
+            .secret("secret")
+            .add_processor(crate::process::Processor::Notice(Box::new(Notices(sender))))
+            .build()
+            .unwrap();
+        tokio::spawn(loop_http_post(bot_ctx.clone()));
+
+        // 未签名与签名错误的事件在解析之前丢弃, 也不会更新 bot 的账号
+        let unsigned = friend_add(3);
+        let status = post_event(&bind, 9, &unsigned, None).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let mis_signed = friend_add(4);
+        let signature = sign("other", &mis_signed);
+        let status = post_event(&bind, 9, &mis_signed, Some(&signature)).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(bot_ctx.id(), 0);
+
+        let signed = friend_add(2);
+        let signature = sign("secret", &signed);
+        let status = post_event(&bind, 1, &signed, Some(&signature)).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        // 第一个到达处理器的就是签名正确的事件
+        let user_id = tokio::time::timeout(Duration::from_secs(1), notices.recv())
+            .await
+            .unwrap();
+        assert_eq!(user_id, Some(2));
+        assert_eq!(bot_ctx.id(), 1);
+        bot_ctx.shutdown().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_keepalive() {
+        use tokio_tungstenite::tungstenite::protocol::Role;
+
+        let bot_ctx = BotContextBuilder::new()
+            .ping_interval(Duration::from_millis(20))
+            .pong_timeout(Duration::from_millis(100))
+            .build()
+            .unwrap();
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        let client = WebSocketStream::from_raw_socket(client, Role::Client, None).await;
+        let mut server = WebSocketStream::from_raw_socket(server, Role::Server, None).await;
+        let running = tokio::spawn(loop_bot(bot_ctx.clone(), client, ClientRole::Universal));
+
+        // 对端持续读取时 tungstenite 自动回复 pong, 连接保持
+        let mut pings = 0;
+        while pings < 5 {
+            let msg = tokio::time::timeout(Duration::from_secs(1), server.next())
+                .await
+                .unwrap();
+            if let Some(Ok(WsMessage::Ping(_))) = msg {
+                pings += 1;
+            }
+        }
+        assert!(!running.is_finished());
+
+        // 对端不再读取, 不回复 pong, 超时后断开
+        tokio::time::timeout(Duration::from_secs(1), running)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(bot_ctx.connection.lock().await.is_none());
+        drop(server);
+    }
+}
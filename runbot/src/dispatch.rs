@@ -0,0 +1,521 @@
+use std::collections::{HashSet, VecDeque};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+
+use futures_util::future::{Either, select};
+use tokio::sync::{Notify, OwnedSemaphorePermit, Semaphore};
+
+/// 等待队列已满时的处理方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// 暂停读取连接, 直到队列有空位。
+    /// 有处理器在等待 action 响应时不暂停 (响应也通过该连接到达), 事件超出容量入队并输出警告,
+    /// 超出的事件达到 `overflow_capacity` 后丢弃最早的事件
+    Wait,
+    /// 丢弃最早的尚未开始处理的事件, 并输出警告
+    DropOldest,
+}
+
+/// 事件分发的调度策略
+///
+/// 同时运行的处理器链不超过 `max_concurrency` 个, 超出的事件排队等待。
+/// 开启 `per_conversation` 后同一个群 (私聊为同一个用户) 的事件按到达顺序依次处理, 不同会话之间并行。
+/// 响应与元事件不经过队列
+#[derive(Debug, Clone)]
+pub struct DispatchPolicy {
+    pub max_concurrency: usize,
+    pub per_conversation: bool,
+    pub queue_capacity: usize,
+    pub overflow: OverflowPolicy,
+    pub overflow_capacity: usize,
+}
+
+impl Default for DispatchPolicy {
+    fn default() -> Self {
+        Self {
+            max_concurrency: 64,
+            per_conversation: false,
+            queue_capacity: 1024,
+            overflow: OverflowPolicy::Wait,
+            overflow_capacity: 1024,
+        }
+    }
+}
+
+impl DispatchPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 同时运行的处理器链数量上限, 默认 64
+    pub fn max_concurrency(self, max_concurrency: usize) -> Self {
+        Self {
+            max_concurrency: max_concurrency.max(1),
+            ..self
+        }
+    }
+
+    /// 同一会话的事件按到达顺序依次处理
+    pub fn per_conversation(self) -> Self {
+        Self {
+            per_conversation: true,
+            ..self
+        }
+    }
+
+    /// 等待处理的事件数量上限, 默认 1024
+    pub fn queue_capacity(self, queue_capacity: usize) -> Self {
+        Self {
+            queue_capacity: queue_capacity.max(1),
+            ..self
+        }
+    }
+
+    /// 队列已满时的处理方式, 默认 `OverflowPolicy::Wait`
+    pub fn overflow(self, overflow: OverflowPolicy) -> Self {
+        Self { overflow, ..self }
+    }
+
+    /// `Wait` 时超出容量入队的事件数量上限, 默认 1024, 达到后丢弃最早的事件
+    pub fn overflow_capacity(self, overflow_capacity: usize) -> Self {
+        Self {
+            overflow_capacity,
+            ..self
+        }
+    }
+}
+
+/// 会话, 群消息以群区分, 其他以用户区分
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Conversation {
+    Group(i64),
+    User(i64),
+}
+
+impl Conversation {
+    fn of(value: &serde_json::Value) -> Option<Self> {
+        let id = |key: &str| {
+            value
+                .get(key)
+                .and_then(|v| v.as_i64())
+                .filter(|id| *id != 0)
+        };
+        if let Some(group_id) = id("group_id") {
+            Some(Conversation::Group(group_id))
+        } else {
+            id("user_id").map(Conversation::User)
+        }
+    }
+}
+
+pub(crate) type Job = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+struct Queued {
+    conversation: Option<Conversation>,
+    job: Job,
+    // 占用的队列位置, 开始处理时释放; 超出容量入队时为 None
+    slot: Option<OwnedSemaphorePermit>,
+}
+
+#[derive(Default)]
+struct DispatchState {
+    queue: VecDeque<Queued>,
+    // 队列中超出容量的事件数量
+    overflowed: usize,
+    running: usize,
+    // 有事件正在处理的会话
+    busy: HashSet<Conversation>,
+}
+
+impl DispatchState {
+    /// 取出下一个可以开始的事件: 未达到并发上限, 且所在会话没有正在处理的事件
+    fn next(&mut self, max_concurrency: usize) -> Option<Queued> {
+        if self.running >= max_concurrency {
+            return None;
+        }
+        let index = self.queue.iter().position(|queued| {
+            queued
+                .conversation
+                .is_none_or(|conversation| !self.busy.contains(&conversation))
+        })?;
+        let queued = self.queue.remove(index)?;
+        if queued.slot.is_none() {
+            self.overflowed -= 1;
+        }
+        self.running += 1;
+        if let Some(conversation) = queued.conversation {
+            self.busy.insert(conversation);
+        }
+        Some(queued)
+    }
+
+    fn push(&mut self, queued: Queued) {
+        if queued.slot.is_none() {
+            self.overflowed += 1;
+        }
+        self.queue.push_back(queued);
+    }
+
+    /// 丢弃最早的尚未开始处理的事件, 释放其占用的队列位置
+    fn drop_oldest(&mut self) -> bool {
+        let Some(dropped) = self.queue.pop_front() else {
+            return false;
+        };
+        if dropped.slot.is_none() {
+            self.overflowed -= 1;
+        }
+        true
+    }
+
+    fn finish(&mut self, conversation: Option<Conversation>) {
+        self.running -= 1;
+        if let Some(conversation) = conversation {
+            self.busy.remove(&conversation);
+        }
+    }
+}
+
+/// BotContext 的事件分发器
+pub(crate) struct Dispatcher {
+    policy: DispatchPolicy,
+    slots: Arc<Semaphore>,
+    state: Mutex<DispatchState>,
+    // 处理器发送了需要等待响应的 action
+    awaiting: Notify,
+}
+
+impl std::fmt::Debug for Dispatcher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Dispatcher")
+            .field("policy", &self.policy)
+            .finish()
+    }
+}
+
+impl Dispatcher {
+    pub(crate) fn new(policy: DispatchPolicy) -> Self {
+        Self {
+            slots: Arc::new(Semaphore::new(policy.queue_capacity)),
+            policy,
+            state: Mutex::new(DispatchState::default()),
+            awaiting: Notify::new(),
+        }
+    }
+
+    /// 有新的 action 等待响应, 唤醒因队列已满而暂停的读取循环
+    pub(crate) fn notify_awaiting(&self) {
+        self.awaiting.notify_waiters();
+    }
+
+    pub(crate) fn depth(&self) -> usize {
+        self.state.lock().unwrap().queue.len()
+    }
+
+    /// 事件加入队列, 按策略等待或丢弃最早的事件, 在读取循环中调用以保持到达顺序
+    ///
+    /// `awaiting_response` 返回 true 时有处理器在等待该连接上的响应, 此时不能暂停读取
+    pub(crate) async fn dispatch(
+        self: &Arc<Self>,
+        event: &serde_json::Value,
+        job: Job,
+        awaiting_response: impl Fn() -> bool,
+    ) {
+        let conversation = if self.policy.per_conversation {
+            Conversation::of(event)
+        } else {
+            None
+        };
+        let slot = match self.policy.overflow {
+            OverflowPolicy::Wait => loop {
+                if let Ok(slot) = self.slots.clone().try_acquire_owned() {
+                    break Some(slot);
+                }
+                // 先注册再检查, 避免漏掉检查之后发送的 action
+                let mut awaiting = std::pin::pin!(self.awaiting.notified());
+                awaiting.as_mut().enable();
+                if awaiting_response() {
+                    let mut state = self.state.lock().unwrap();
+                    if state.overflowed < self.policy.overflow_capacity {
+                        tracing::warn!(
+                            "dispatch queue full while processors wait for responses, event queued over capacity"
+                        );
+                        break None;
+                    }
+                    state.drop_oldest();
+                    tracing::warn!("dispatch queue overflow full, the oldest event is dropped");
+                    continue;
+                }
+                // 等待空位, 期间处理器发送新的 action 时不再暂停读取
+                match select(std::pin::pin!(self.acquire()), awaiting).await {
+                    Either::Left((slot, _)) => break Some(slot),
+                    Either::Right(_) => continue,
+                }
+            },
+            OverflowPolicy::DropOldest => loop {
+                if let Ok(slot) = self.slots.clone().try_acquire_owned() {
+                    break Some(slot);
+                }
+                if !self.state.lock().unwrap().drop_oldest() {
+                    break Some(self.acquire().await);
+                }
+                tracing::warn!("dispatch queue full, the oldest event is dropped");
+            },
+        };
+        self.state.lock().unwrap().push(Queued {
+            conversation,
+            job,
+            slot,
+        });
+        self.schedule();
+    }
+
+    async fn acquire(&self) -> OwnedSemaphorePermit {
+        self.slots
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("dispatch semaphore closed")
+    }
+
+    fn schedule(self: &Arc<Self>) {
+        // 不持有锁启动任务: 运行时关闭时 spawn 会立即释放任务, Running 的 drop 需要获取锁
+        loop {
+            let next = self.state.lock().unwrap().next(self.policy.max_concurrency);
+            let Some(Queued {
+                conversation,
+                job,
+                slot,
+            }) = next
+            else {
+                break;
+            };
+            drop(slot);
+            let running = Running {
+                dispatcher: self.clone(),
+                conversation,
+            };
+            tokio::spawn(async move {
+                job.await;
+                drop(running);
+            });
+        }
+    }
+}
+
+/// 处理结束 (包括 panic) 时释放并发与会话, 并启动后续事件
+struct Running {
+    dispatcher: Arc<Dispatcher>,
+    conversation: Option<Conversation>,
+}
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        self.dispatcher
+            .state
+            .lock()
+            .unwrap()
+            .finish(self.conversation);
+        self.dispatcher.schedule();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn queued(slots: &Arc<Semaphore>, conversation: Option<Conversation>) -> Queued {
+        Queued {
+            conversation,
+            job: Box::pin(async {}),
+            slot: Some(slots.clone().try_acquire_owned().unwrap()),
+        }
+    }
+
+    #[test]
+    fn test_conversation() {
+        assert_eq!(
+            Conversation::of(&json!({"group_id": 1, "user_id": 2})),
+            Some(Conversation::Group(1))
+        );
+        assert_eq!(
+            Conversation::of(&json!({"group_id": 0, "user_id": 2})),
+            Some(Conversation::User(2))
+        );
+        assert_eq!(Conversation::of(&json!({"echo": "e"})), None);
+    }
+
+    #[test]
+    fn test_next() {
+        let slots = Arc::new(Semaphore::new(8));
+        let mut state = DispatchState::default();
+        let group = Some(Conversation::Group(1));
+        state.queue.push_back(queued(&slots, group));
+        state.queue.push_back(queued(&slots, group));
+        state
+            .queue
+            .push_back(queued(&slots, Some(Conversation::User(2))));
+        state.queue.push_back(queued(&slots, None));
+
+        assert_eq!(state.next(2).unwrap().conversation, group);
+        // 同一会话的第二个事件需要等待, 其他会话可以开始
+        assert_eq!(
+            state.next(2).unwrap().conversation,
+            Some(Conversation::User(2))
+        );
+        // 达到并发上限
+        assert!(state.next(2).is_none());
+        state.finish(Some(Conversation::User(2)));
+        assert_eq!(state.next(2).unwrap().conversation, None);
+        assert!(state.next(3).is_none());
+        state.finish(group);
+        assert_eq!(state.next(3).unwrap().conversation, group);
+        assert!(state.queue.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_dispatch_in_order() {
+        let dispatcher = Arc::new(Dispatcher::new(
+            DispatchPolicy::new().max_concurrency(4).per_conversation(),
+        ));
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        for i in 0..8u64 {
+            let tx = tx.clone();
+            let event = json!({"group_id": 1 + i % 2, "user_id": 3});
+            dispatcher
+                .dispatch(
+                    &event,
+                    Box::pin(async move {
+                        // 先到的事件耗时更长, 依然先完成
+                        tokio::time::sleep(std::time::Duration::from_millis(40 - i * 5)).await;
+                        tx.send(i).unwrap();
+                    }),
+                    || false,
+                )
+                .await;
+        }
+        drop(tx);
+        let mut finished = vec![];
+        while let Some(i) = rx.recv().await {
+            finished.push(i);
+        }
+        let group =
+            |rem: u64| -> Vec<u64> { finished.iter().copied().filter(|i| i % 2 == rem).collect() };
+        assert_eq!(group(0), vec![0, 2, 4, 6]);
+        assert_eq!(group(1), vec![1, 3, 5, 7]);
+    }
+
+    #[tokio::test]
+    async fn test_wait_overflow_capacity() {
+        let dispatcher = Arc::new(Dispatcher::new(
+            DispatchPolicy::new()
+                .max_concurrency(1)
+                .queue_capacity(1)
+                .overflow_capacity(2),
+        ));
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let (release, blocked) = tokio::sync::oneshot::channel::<()>();
+        let mut blocked = Some(blocked);
+        let awaiting = Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let event = json!({});
+        for i in 0..6u64 {
+            let tx = tx.clone();
+            let blocked = blocked.take();
+            let job = Box::pin(async move {
+                if let Some(blocked) = blocked {
+                    let _ = blocked.await;
+                }
+                tx.send(i).unwrap();
+            });
+            let check = awaiting.clone();
+            let dispatch = dispatcher.dispatch(&event, job, move || {
+                check.load(std::sync::atomic::Ordering::SeqCst)
+            });
+            if i == 2 {
+                // 队列已满时暂停, 处理器发送 action 后不再等待
+                let mut dispatch = std::pin::pin!(dispatch);
+                assert!(
+                    tokio::time::timeout(std::time::Duration::from_millis(50), dispatch.as_mut())
+                        .await
+                        .is_err()
+                );
+                awaiting.store(true, std::sync::atomic::Ordering::SeqCst);
+                dispatcher.notify_awaiting();
+                dispatch.await;
+            } else {
+                dispatch.await;
+            }
+        }
+        // 超出容量的事件不超过 overflow_capacity, 最早的事件被丢弃
+        assert_eq!(dispatcher.depth(), 3);
+        drop(tx);
+        release.send(()).unwrap();
+        let mut finished = vec![];
+        while let Some(i) = rx.recv().await {
+            finished.push(i);
+        }
+        assert_eq!(finished, vec![0, 3, 4, 5]);
+    }
+
+    #[derive(Debug)]
+    struct Reply;
+
+    #[async_trait::async_trait]
+    impl crate::process::MessageProcessor for Reply {
+        fn id(&self) -> &'static str {
+            "reply"
+        }
+
+        async fn process_message(
+            &self,
+            bot_ctx: Arc<crate::bot_context::BotContext>,
+            message: &crate::event::Message,
+        ) -> anyhow::Result<bool> {
+            let response = bot_ctx
+                .send_group_message(message.group_id, message.raw_message.clone())
+                .await?;
+            response.wait_response().await?;
+            Ok(true)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_wait_keeps_reading_responses() {
+        use crate::process::Processor;
+        use crate::testing::MockOneBot;
+
+        let mock = MockOneBot::start().await.unwrap();
+        let bot_ctx = crate::bot_context::BotContextBuilder::new()
+            .url(mock.url())
+            .dispatch(
+                DispatchPolicy::new()
+                    .max_concurrency(1)
+                    .queue_capacity(1)
+                    .overflow(OverflowPolicy::Wait),
+            )
+            .add_processor(Processor::Message(Box::new(Reply)))
+            .build()
+            .unwrap();
+        tokio::spawn(crate::connection::loop_client(bot_ctx.clone()));
+        mock.wait_connected().await;
+
+        // 队列已满时处理器仍在等待响应, 读取循环不能因此暂停
+        for i in 0..5 {
+            mock.push_group_message(1, 2, format!("m{}", i));
+        }
+        for i in 0..5 {
+            mock.expect_action("send_group_msg")
+                .with_text(format!("m{}", i))
+                .await;
+        }
+        tokio::time::timeout(std::time::Duration::from_secs(5), async {
+            while bot_ctx.in_flight() > 0 {
+                tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        bot_ctx.shutdown().await.unwrap();
+    }
+}
@@ -14,6 +14,20 @@ pub enum Error {
     WebSocketError(#[from] tokio_tungstenite::tungstenite::Error),
     #[error("timeout error: {0}")]
     TimeoutError(String, tokio::time::error::Elapsed),
+    #[error("io error: {0}")]
+    IoError(#[from] std::io::Error),
+    #[error("http error: {0}")]
+    HttpError(String),
+    #[error("signature error: {0}")]
+    SignatureError(String),
+    #[error("tls error: {0}")]
+    TlsError(String),
+    #[error("proxy error: {0}")]
+    ProxyError(String),
+    #[error("not connected: {0}")]
+    NotConnected(String),
+    #[error("connection lost: {0}")]
+    ConnectionLost(String),
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
@@ -0,0 +1,147 @@
+use std::fmt::{self, Debug, Display};
+use std::sync::Arc;
+
+use crate::bot_context::BotContext;
+use crate::event::{MessageType, Post};
+
+/// 需要告诉用户的错误, 例如命令参数错误
+///
+/// 默认的 `ErrorPolicy` 会把消息回复给发送者, 其他错误视为内部错误只记录日志
+#[derive(Debug, Clone)]
+pub struct UserError(pub String);
+
+impl UserError {
+    pub fn new(message: impl Into<String>) -> Self {
+        Self(message.into())
+    }
+}
+
+impl Display for UserError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for UserError {}
+
+/// `anyhow::bail!` 之外的简写: `return Err(user_error("参数错误"))`
+pub fn user_error(message: impl Into<String>) -> anyhow::Error {
+    UserError::new(message).into()
+}
+
+/// 处理器返回的错误
+#[derive(Debug)]
+pub struct ProcessorError<'a> {
+    pub processor_id: &'static str,
+    pub post: &'a Post,
+    pub error: &'a anyhow::Error,
+}
+
+impl ProcessorError<'_> {
+    /// 错误链中的 `UserError` 的消息, 内部错误为 None
+    pub fn user_message(&self) -> Option<&str> {
+        self.error
+            .chain()
+            .find_map(|error| error.downcast_ref::<UserError>())
+            .map(|error| error.0.as_str())
+    }
+}
+
+/// 处理器出错后的处理方式
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ErrorAction {
+    /// 继续调用后续的处理器
+    Continue,
+    /// 停止处理器链
+    Stop,
+    /// 回复给消息的发送者 (群消息回复到群), 然后停止处理器链
+    Reply(String),
+}
+
+/// 已经按 `ErrorPolicy` 停止处理器链的错误, 作为 context 附加在原错误上,
+/// 模块外层的处理器链不再重复记录和处理
+#[derive(Debug)]
+pub(crate) struct PolicyStopped;
+
+impl Display for PolicyStopped {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("processor chain stopped by error policy")
+    }
+}
+
+/// 处理器出错时的策略, 通过 `BotContextBuilder::error_policy` 设置
+///
+/// 默认: `UserError` 回复给用户, 其他错误停止处理器链
+#[derive(Clone)]
+pub struct ErrorPolicy(Arc<dyn Fn(&ProcessorError<'_>) -> ErrorAction + Send + Sync>);
+
+impl ErrorPolicy {
+    pub fn new<F>(decide: F) -> Self
+    where
+        F: Fn(&ProcessorError<'_>) -> ErrorAction + Send + Sync + 'static,
+    {
+        Self(Arc::new(decide))
+    }
+
+    pub(crate) fn decide(&self, error: &ProcessorError<'_>) -> ErrorAction {
+        (self.0)(error)
+    }
+}
+
+impl Default for ErrorPolicy {
+    fn default() -> Self {
+        Self::new(|error| match error.user_message() {
+            Some(message) => ErrorAction::Reply(message.to_string()),
+            None => ErrorAction::Stop,
+        })
+    }
+}
+
+impl Debug for ErrorPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ErrorPolicy")
+    }
+}
+
+/// 把错误消息回复给事件的来源, 目前只支持消息事件
+pub(crate) async fn reply_error(bot_ctx: &BotContext, post: &Post, text: String) {
+    let result = match post {
+        Post::Message(message) => match message.message_type {
+            MessageType::Group => bot_ctx.send_group_message(message.group_id, text).await,
+            _ => bot_ctx.send_private_message(message.user_id, text).await,
+        },
+        _ => {
+            tracing::warn!("error reply is only supported for messages: {}", text);
+            return;
+        }
+    };
+    if let Err(err) = result {
+        tracing::warn!("reply error message failed: {:?}", err);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::Message;
+
+    #[test]
+    fn test_default_policy() {
+        let post = Post::Message(Message::default());
+        let policy = ErrorPolicy::default();
+        let error = user_error("参数错误").context("parse command");
+        let action = policy.decide(&ProcessorError {
+            processor_id: "demo",
+            post: &post,
+            error: &error,
+        });
+        assert_eq!(action, ErrorAction::Reply("参数错误".to_string()));
+        let error = anyhow::anyhow!("database unavailable");
+        let action = policy.decide(&ProcessorError {
+            processor_id: "demo",
+            post: &post,
+            error: &error,
+        });
+        assert_eq!(action, ErrorAction::Stop);
+    }
+}
@@ -608,6 +608,128 @@ pub enum GroupRequestSubType {
     Unknown(String),
 }
 
+impl Message {
+    /// 消息中是否 @ 了 bot (`self_id`)
+    pub fn is_at_me(&self) -> bool {
+        let self_id = self.self_id.to_string();
+        self.message
+            .iter()
+            .any(|data| matches!(data, MessageData::At(MessageAt { qq, .. }) if *qq == self_id))
+    }
+}
+
+impl Notice {
+    /// 通知所在的群, 好友通知与私聊戳一戳为 None
+    pub fn group_id(&self) -> Option<i64> {
+        let group_id = match self {
+            Notice::GroupUpload(notice) => notice.group_id,
+            Notice::GroupAdmin(notice) => notice.group_id,
+            Notice::GroupDecrease(notice) => notice.group_id,
+            Notice::GroupIncrease(notice) => notice.group_id,
+            Notice::GroupBan(notice) => notice.group_id,
+            Notice::GroupRecall(notice) => notice.group_id,
+            Notice::FriendAdd(_) | Notice::FriendRecall(_) => 0,
+            Notice::Notify(Notify::Poke(notify)) => notify.group_id,
+            Notice::Notify(Notify::LuckyKing(notify)) => notify.group_id,
+            Notice::Notify(Notify::Honor(notify)) => notify.group_id,
+            Notice::Notify(Notify::Unknown(value)) | Notice::Unknown(value) => {
+                value["group_id"].as_i64().unwrap_or_default()
+            }
+        };
+        Some(group_id).filter(|group_id| *group_id != 0)
+    }
+
+    /// 通知涉及的用户
+    pub fn user_id(&self) -> Option<i64> {
+        let user_id = match self {
+            Notice::GroupUpload(notice) => notice.user_id,
+            Notice::GroupAdmin(notice) => notice.user_id,
+            Notice::GroupDecrease(notice) => notice.user_id,
+            Notice::GroupIncrease(notice) => notice.user_id,
+            Notice::GroupBan(notice) => notice.user_id,
+            Notice::GroupRecall(notice) => notice.user_id,
+            Notice::FriendAdd(notice) => notice.user_id,
+            Notice::FriendRecall(notice) => notice.user_id,
+            Notice::Notify(Notify::Poke(notify)) => notify.user_id,
+            Notice::Notify(Notify::LuckyKing(notify)) => notify.user_id,
+            Notice::Notify(Notify::Honor(notify)) => notify.user_id,
+            Notice::Notify(Notify::Unknown(value)) | Notice::Unknown(value) => {
+                value["user_id"].as_i64().unwrap_or_default()
+            }
+        };
+        Some(user_id).filter(|user_id| *user_id != 0)
+    }
+
+    /// 通知的 sub_type, 与 OneBot 中的字符串相同, 没有 sub_type 的通知为 None
+    pub fn sub_type(&self) -> Option<&str> {
+        let sub_type = match self {
+            Notice::GroupAdmin(notice) => match notice.sub_type {
+                GroupAdminSubType::Set => "set",
+                GroupAdminSubType::UnSet => "unset",
+            },
+            Notice::GroupDecrease(notice) => match notice.sub_type {
+                GroupDecreaseSubType::Leave => "leave",
+                GroupDecreaseSubType::Kick => "kick",
+                GroupDecreaseSubType::KickMe => "kick_me",
+            },
+            Notice::GroupIncrease(notice) => match notice.sub_type {
+                GroupIncreaseSubType::Approve => "approve",
+                GroupIncreaseSubType::Invite => "invite",
+            },
+            Notice::GroupBan(notice) => match notice.sub_type {
+                GroupBanSubType::Ban => "ban",
+                GroupBanSubType::LiftBan => "lift_ban",
+            },
+            Notice::Notify(Notify::Poke(_)) => "poke",
+            Notice::Notify(Notify::LuckyKing(_)) => "lucky_king",
+            Notice::Notify(Notify::Honor(_)) => "honor",
+            Notice::Notify(Notify::Unknown(value)) | Notice::Unknown(value) => {
+                return value["sub_type"].as_str();
+            }
+            Notice::GroupUpload(_)
+            | Notice::FriendAdd(_)
+            | Notice::GroupRecall(_)
+            | Notice::FriendRecall(_) => return None,
+        };
+        Some(sub_type)
+    }
+}
+
+impl Request {
+    /// 加群请求所在的群, 好友请求为 None
+    pub fn group_id(&self) -> Option<i64> {
+        let group_id = match self {
+            Request::Group(request) => request.group_id,
+            Request::Friend(_) => 0,
+            Request::Unknown(value) => value["group_id"].as_i64().unwrap_or_default(),
+        };
+        Some(group_id).filter(|group_id| *group_id != 0)
+    }
+
+    /// 发起请求的用户
+    pub fn user_id(&self) -> Option<i64> {
+        let user_id = match self {
+            Request::Group(request) => request.user_id,
+            Request::Friend(request) => request.user_id,
+            Request::Unknown(value) => value["user_id"].as_i64().unwrap_or_default(),
+        };
+        Some(user_id).filter(|user_id| *user_id != 0)
+    }
+
+    /// 请求的 sub_type, 好友请求为 None
+    pub fn sub_type(&self) -> Option<&str> {
+        match self {
+            Request::Group(request) => Some(match &request.sub_type {
+                GroupRequestSubType::Add => "add",
+                GroupRequestSubType::Invite => "invite",
+                GroupRequestSubType::Unknown(sub_type) => sub_type.as_str(),
+            }),
+            Request::Friend(_) => None,
+            Request::Unknown(value) => value["sub_type"].as_str(),
+        }
+    }
+}
+
 impl Post {
     pub fn parse(value: &serde_json::Value) -> Result<Post> {
         if value.get("retcode").is_some() {
@@ -0,0 +1,227 @@
+use std::fmt::{self, Debug};
+use std::sync::Arc;
+
+use crate::bot_context::BotContext;
+use crate::event::Heartbeat;
+use tokio::time::{Duration, Instant};
+
+/// 心跳看门狗
+///
+/// 连接后开始计时, `first_heartbeat_timeout` 内没有收到第一个心跳,
+/// 或超过 `multiplier` 倍的心跳间隔仍未收到新的心跳, 或者心跳上报 `status.online` 为 false 时,
+/// 断开连接 (loop_client 随后按重连策略重连)
+#[derive(Debug, Clone)]
+pub struct HeartbeatWatchdog {
+    pub multiplier: u32,
+    pub first_heartbeat_timeout: Duration,
+    pub(crate) observer: Option<UnhealthyObserver>,
+}
+
+impl Default for HeartbeatWatchdog {
+    fn default() -> Self {
+        Self {
+            multiplier: 3,
+            first_heartbeat_timeout: Duration::from_secs(60),
+            observer: None,
+        }
+    }
+}
+
+impl HeartbeatWatchdog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 允许错过的心跳间隔倍数, 默认 3
+    pub fn multiplier(self, multiplier: u32) -> Self {
+        Self {
+            multiplier: multiplier.max(1),
+            ..self
+        }
+    }
+
+    /// 连接后等待第一个心跳的时长, 默认 60 秒, 应大于 onebot 实现的心跳间隔
+    pub fn first_heartbeat_timeout(self, first_heartbeat_timeout: Duration) -> Self {
+        Self {
+            first_heartbeat_timeout,
+            ..self
+        }
+    }
+
+    /// bot 被判定为不健康, 即将断开连接时调用
+    pub fn on_unhealthy(
+        self,
+        on_unhealthy: impl Fn(&BotContext, &Unhealthy) + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            observer: Some(UnhealthyObserver(Arc::new(on_unhealthy))),
+            ..self
+        }
+    }
+
+    /// 根据心跳上报的间隔 (毫秒) 计算等待下一个心跳的时长, 溢出时视为不限时
+    pub(crate) fn timeout(&self, heartbeat: &Heartbeat) -> Option<Duration> {
+        if heartbeat.interval <= 0 {
+            return None;
+        }
+        Duration::from_millis(heartbeat.interval as u64).checked_mul(self.multiplier)
+    }
+
+    pub(crate) fn notify(&self, bot_ctx: &BotContext, unhealthy: &Unhealthy) {
+        tracing::warn!("bot {} unhealthy: {:?}", bot_ctx.id(), unhealthy);
+        if let Some(observer) = &self.observer {
+            (observer.0)(bot_ctx, unhealthy);
+        }
+    }
+}
+
+/// bot 不健康的原因
+#[derive(Debug, Clone)]
+pub enum Unhealthy {
+    /// 超时未收到心跳, 附带等待的时长
+    HeartbeatTimeout(Duration),
+    /// 心跳上报 `status.online` 为 false
+    Offline,
+}
+
+type UnhealthyFn = dyn Fn(&BotContext, &Unhealthy) + Send + Sync;
+
+#[derive(Clone)]
+pub struct UnhealthyObserver(pub(crate) Arc<UnhealthyFn>);
+
+impl Debug for UnhealthyObserver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("UnhealthyObserver")
+    }
+}
+
+/// 持续检查 bot 的心跳, 判定为不健康时返回原因
+pub(crate) async fn watch_heartbeat(
+    bot_ctx: Arc<BotContext>,
+    watchdog: HeartbeatWatchdog,
+) -> Unhealthy {
+    let mut heartbeat_rx = bot_ctx.heartbeat.subscribe();
+    heartbeat_rx.mark_unchanged();
+    // 连接后即开始计时, 对端一直不发送心跳时同样判定为不健康
+    let first_heartbeat_timeout = watchdog.first_heartbeat_timeout;
+    let mut deadline = Instant::now()
+        .checked_add(first_heartbeat_timeout)
+        .map(|deadline| (deadline, first_heartbeat_timeout));
+    loop {
+        match deadline {
+            Some((deadline, timeout)) => {
+                if tokio::time::timeout_at(deadline, heartbeat_rx.changed())
+                    .await
+                    .is_err()
+                {
+                    return Unhealthy::HeartbeatTimeout(timeout);
+                }
+            }
+            None => {
+                // bot_ctx 持有 sender, changed 不会返回错误
+                let _ = heartbeat_rx.changed().await;
+            }
+        }
+        let heartbeat = heartbeat_rx.borrow_and_update().clone();
+        if let Some(heartbeat) = heartbeat {
+            if !heartbeat.status.online {
+                return Unhealthy::Offline;
+            }
+            deadline = watchdog.timeout(&heartbeat).and_then(|timeout| {
+                Instant::now()
+                    .checked_add(timeout)
+                    .map(|deadline| (deadline, timeout))
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bot_context::BotContextBuilder;
+    use crate::connection::loop_client;
+    use crate::event::{HeartbeatStatus, PostType};
+    use crate::reconnect::ReconnectPolicy;
+    use crate::testing::MockOneBot;
+    use tokio::sync::mpsc;
+
+    fn heartbeat(interval: i64) -> Heartbeat {
+        Heartbeat {
+            time: 0,
+            self_id: 1,
+            post_type: PostType::MetaEvent,
+            status: HeartbeatStatus {
+                online: true,
+                good: true,
+            },
+            interval,
+        }
+    }
+
+    #[test]
+    fn test_timeout() {
+        let watchdog = HeartbeatWatchdog::new().multiplier(2);
+        assert_eq!(
+            watchdog.timeout(&heartbeat(5000)),
+            Some(Duration::from_secs(10))
+        );
+        assert_eq!(watchdog.timeout(&heartbeat(0)), None);
+        let watchdog = HeartbeatWatchdog::new().multiplier(0);
+        assert_eq!(
+            watchdog.timeout(&heartbeat(5000)),
+            Some(Duration::from_secs(5))
+        );
+        // 对端上报的间隔过大时不设置期限
+        let watchdog = HeartbeatWatchdog::new().multiplier(u32::MAX);
+        assert_eq!(watchdog.timeout(&heartbeat(i64::MAX)), None);
+    }
+
+    #[tokio::test]
+    async fn test_watchdog_reconnects() {
+        let mock = MockOneBot::start().await.unwrap();
+        let (tx, mut rx) = mpsc::unbounded_channel::<String>();
+        let (unhealthy_tx, connect_tx) = (tx.clone(), tx);
+        let watchdog = HeartbeatWatchdog::new()
+            .multiplier(2)
+            .first_heartbeat_timeout(Duration::from_millis(200))
+            .on_unhealthy(move |_, unhealthy| {
+                unhealthy_tx.send(format!("{:?}", unhealthy)).unwrap();
+            });
+        let bot_ctx = BotContextBuilder::new()
+            .url(mock.url())
+            .reconnect_policy(ReconnectPolicy::fixed(Duration::from_millis(50)))
+            .heartbeat_watchdog(watchdog)
+            .on_connect(move |_| {
+                let tx = connect_tx.clone();
+                async move { tx.send("connect".to_string()).unwrap() }
+            })
+            .build()
+            .unwrap();
+        let mut next = async || {
+            tokio::time::timeout(Duration::from_secs(5), rx.recv())
+                .await
+                .unwrap()
+                .unwrap()
+        };
+        tokio::spawn(loop_client(bot_ctx.clone()));
+        assert_eq!(next().await, "connect");
+
+        // 连接后一直没有心跳
+        assert_eq!(next().await, "HeartbeatTimeout(200ms)");
+        assert_eq!(next().await, "connect");
+
+        // 收到心跳后停止发送, 超过 2 倍间隔断开
+        mock.wait_connected().await;
+        mock.push_heartbeat(50, true);
+        assert_eq!(next().await, "HeartbeatTimeout(100ms)");
+        assert_eq!(next().await, "connect");
+
+        // 心跳上报离线
+        mock.wait_connected().await;
+        mock.push_heartbeat(50, false);
+        assert_eq!(next().await, "Offline");
+        assert_eq!(next().await, "connect");
+        bot_ctx.shutdown().await.unwrap();
+    }
+}
@@ -0,0 +1,143 @@
+use std::fmt::{self, Debug};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+
+use crate::bot_context::BotContext;
+use crate::event::Lifecycle;
+
+pub type HookFuture = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+type ConnectionFn = dyn Fn(Arc<BotContext>) -> HookFuture + Send + Sync;
+type LifecycleFn = dyn Fn(Arc<BotContext>, Lifecycle) -> HookFuture + Send + Sync;
+
+/// 连接的生命周期回调, 通过 `BotContextBuilder` / `BotServerBuilder` 的 `on_connect` 等方法设置
+///
+/// 回调在新的任务中运行, 可以在回调中调用 action (例如 `get_group_list` 预热缓存)
+#[derive(Clone, Default)]
+pub struct ConnectionHooks {
+    pub(crate) on_connect: Option<Arc<ConnectionFn>>,
+    pub(crate) on_disconnect: Option<Arc<ConnectionFn>>,
+    pub(crate) on_lifecycle: Option<Arc<LifecycleFn>>,
+}
+
+impl Debug for ConnectionHooks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ConnectionHooks")
+            .field("on_connect", &self.on_connect.is_some())
+            .field("on_disconnect", &self.on_disconnect.is_some())
+            .field("on_lifecycle", &self.on_lifecycle.is_some())
+            .finish()
+    }
+}
+
+impl ConnectionHooks {
+    pub(crate) fn set_on_connect<F, Fut>(&mut self, on_connect: F)
+    where
+        F: Fn(Arc<BotContext>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.on_connect = Some(Arc::new(move |bot_ctx| Box::pin(on_connect(bot_ctx))));
+    }
+
+    pub(crate) fn set_on_disconnect<F, Fut>(&mut self, on_disconnect: F)
+    where
+        F: Fn(Arc<BotContext>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.on_disconnect = Some(Arc::new(move |bot_ctx| Box::pin(on_disconnect(bot_ctx))));
+    }
+
+    pub(crate) fn set_on_lifecycle<F, Fut>(&mut self, on_lifecycle: F)
+    where
+        F: Fn(Arc<BotContext>, Lifecycle) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.on_lifecycle = Some(Arc::new(move |bot_ctx, lifecycle| {
+            Box::pin(on_lifecycle(bot_ctx, lifecycle))
+        }));
+    }
+
+    pub(crate) fn connected(&self, bot_ctx: &Arc<BotContext>) {
+        if let Some(on_connect) = &self.on_connect {
+            tokio::spawn(on_connect(bot_ctx.clone()));
+        }
+    }
+
+    pub(crate) fn disconnected(&self, bot_ctx: &Arc<BotContext>) {
+        if let Some(on_disconnect) = &self.on_disconnect {
+            tokio::spawn(on_disconnect(bot_ctx.clone()));
+        }
+    }
+
+    pub(crate) fn lifecycle(&self, bot_ctx: &Arc<BotContext>, lifecycle: &Lifecycle) {
+        if let Some(on_lifecycle) = &self.on_lifecycle {
+            tokio::spawn(on_lifecycle(bot_ctx.clone(), lifecycle.clone()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bot_context::BotContextBuilder;
+    use crate::connection::loop_client;
+    use crate::reconnect::ReconnectPolicy;
+    use crate::testing::MockOneBot;
+    use tokio::sync::mpsc;
+    use tokio::time::Duration;
+
+    #[tokio::test]
+    async fn test_hooks() {
+        let mock = MockOneBot::start().await.unwrap();
+        let (tx, mut rx) = mpsc::unbounded_channel::<String>();
+        let (connect_tx, disconnect_tx, lifecycle_tx) = (tx.clone(), tx.clone(), tx);
+        let bot_ctx = BotContextBuilder::new()
+            .url(mock.url())
+            .reconnect_policy(ReconnectPolicy::fixed(Duration::from_millis(50)))
+            .on_connect(move |_| {
+                let tx = connect_tx.clone();
+                async move { tx.send("connect".to_string()).unwrap() }
+            })
+            .on_disconnect(move |_| {
+                let tx = disconnect_tx.clone();
+                async move { tx.send("disconnect".to_string()).unwrap() }
+            })
+            .on_lifecycle(move |bot_ctx, lifecycle| {
+                let tx = lifecycle_tx.clone();
+                async move {
+                    tx.send(format!("{:?} {}", lifecycle.sub_type, bot_ctx.id()))
+                        .unwrap()
+                }
+            })
+            .build()
+            .unwrap();
+        let mut next = async || {
+            tokio::time::timeout(Duration::from_secs(5), rx.recv())
+                .await
+                .unwrap()
+                .unwrap()
+        };
+        tokio::spawn(loop_client(bot_ctx.clone()));
+        // 回调在新的任务中运行, 连接与 lifecycle 之间的顺序不确定
+        let mut connected = vec![next().await, next().await];
+        connected.sort();
+        assert_eq!(connected, vec!["Connect 10000", "connect"]);
+
+        mock.disconnect();
+        assert_eq!(next().await, "disconnect");
+        // 按重连策略重新连接
+        let mut connected = vec![next().await, next().await];
+        connected.sort();
+        assert_eq!(connected, vec!["Connect 10000", "connect"]);
+
+        // 不经过连接, 直接处理 lifecycle 元事件
+        let enable = serde_json::json!({
+            "time": 0, "self_id": 42, "post_type": "meta_event",
+            "meta_event_type": "lifecycle", "sub_type": "enable",
+        })
+        .to_string();
+        bot_ctx.handle_text(bot_ctx.clone(), &enable).await;
+        assert_eq!(next().await, "Enable 42");
+        bot_ctx.shutdown().await.unwrap();
+    }
+}
@@ -1,20 +1,48 @@
 pub mod bot_context;
+pub mod bot_manager;
 pub mod command;
 pub mod common;
 pub mod connection;
+pub mod dispatch;
 pub mod error;
+pub mod error_policy;
 pub mod event;
+pub mod heartbeat;
+pub mod hooks;
+pub mod metrics;
+pub mod middleware;
 pub mod module;
+pub mod offline;
 pub mod onebot11_api;
+pub mod outbound;
 pub mod process;
+pub mod proxy;
 pub mod re_export;
+pub mod reconnect;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+pub mod tls;
+pub mod traffic;
 
 pub mod prelude {
     pub use crate::bot_context::*;
+    pub use crate::bot_manager::*;
     pub use crate::connection::*;
+    pub use crate::dispatch::*;
+    pub use crate::error_policy::*;
     pub use crate::event::*;
+    pub use crate::heartbeat::*;
+    pub use crate::hooks::*;
+    pub use crate::metrics::*;
+    pub use crate::middleware::*;
     pub use crate::module::*;
+    pub use crate::offline::*;
     pub use crate::onebot11_api::*;
+    pub use crate::outbound::*;
     pub use crate::process::*;
+    pub use crate::proxy::*;
+    pub use crate::reconnect::*;
+    pub use crate::tls::*;
+    pub use crate::traffic::*;
     pub use runbot_codegen::{module, processor};
 }
@@ -0,0 +1,353 @@
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex, Weak};
+
+use crate::bot_context::EchoNotifer;
+use crate::error::Result;
+use crate::event::{Post, Response};
+use bytes::Bytes;
+use dashmap::DashMap;
+use http_body_util::Full;
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper_util::rt::TokioIo;
+use tokio::net::TcpListener;
+use tokio::time::{Duration, Instant};
+
+/// 直方图的桶 (秒)
+const BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// 超过这个时间仍未收到响应的 action 不再统计耗时
+const IN_FLIGHT_TTL: Duration = Duration::from_secs(600);
+
+/// 事件、处理器与 action 的统计, 以 Prometheus 文本格式输出
+///
+/// 通过 `BotContextBuilder::metrics` / `BotServerBuilder::metrics` 启用, 多个 bot 可以共用一个
+#[derive(Debug, Default)]
+pub struct Metrics {
+    state: Mutex<MetricsState>,
+    // echo -> (action, 发送时间)
+    in_flight: DashMap<String, (String, Instant)>,
+    echo_notifers: Mutex<Vec<Weak<EchoNotiferMap>>>,
+}
+
+type EchoNotiferMap = DashMap<String, tokio::sync::mpsc::Sender<Response>>;
+
+#[derive(Debug, Default)]
+struct MetricsState {
+    events: BTreeMap<&'static str, u64>,
+    parse_errors: u64,
+    processor_durations: BTreeMap<String, Histogram>,
+    processor_results: BTreeMap<(String, &'static str), u64>,
+    action_durations: BTreeMap<String, Histogram>,
+    action_responses: BTreeMap<(String, i64), u64>,
+}
+
+#[derive(Debug, Default)]
+struct Histogram {
+    buckets: [u64; BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, value: Duration) {
+        let value = value.as_secs_f64();
+        if let Some(index) = BUCKETS.iter().position(|bucket| value <= *bucket) {
+            self.buckets[index] += 1;
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+
+    fn render(&self, out: &mut String, name: &str, label: &str, value: &str) {
+        let mut cumulative = 0;
+        for (bucket, count) in BUCKETS.iter().zip(self.buckets) {
+            cumulative += count;
+            let _ = writeln!(
+                out,
+                "{name}_bucket{{{label}=\"{}\",le=\"{bucket}\"}} {cumulative}",
+                escape(value)
+            );
+        }
+        let _ = writeln!(
+            out,
+            "{name}_bucket{{{label}=\"{}\",le=\"+Inf\"}} {}",
+            escape(value),
+            self.count
+        );
+        let _ = writeln!(
+            out,
+            "{name}_sum{{{label}=\"{}\"}} {}",
+            escape(value),
+            self.sum
+        );
+        let _ = writeln!(
+            out,
+            "{name}_count{{{label}=\"{}\"}} {}",
+            escape(value),
+            self.count
+        );
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub(crate) fn watch_echoes(&self, echo_notifer: &EchoNotifer) {
+        let mut echo_notifers = self.echo_notifers.lock().unwrap();
+        echo_notifers.retain(|echo_notifer| echo_notifer.strong_count() > 0);
+        echo_notifers.push(Arc::downgrade(echo_notifer));
+    }
+
+    pub(crate) fn event(&self, post: &Post) {
+        let post_type = match post {
+            Post::MetaEvent(_) => "meta_event",
+            Post::Response(_) => "response",
+            Post::Message(_) => "message",
+            Post::Notice(_) => "notice",
+            Post::Request(_) => "request",
+            Post::MessageSent(_) => "message_sent",
+            Post::Unknown(_) => "unknown",
+        };
+        *self
+            .state
+            .lock()
+            .unwrap()
+            .events
+            .entry(post_type)
+            .or_default() += 1;
+    }
+
+    pub(crate) fn parse_error(&self) {
+        self.state.lock().unwrap().parse_errors += 1;
+    }
+
+    pub(crate) fn processor(&self, id: &str, elapsed: Duration, result: &anyhow::Result<bool>) {
+        let result = match result {
+            Ok(true) => "handled",
+            Ok(false) => "skipped",
+            Err(_) => "error",
+        };
+        let mut state = self.state.lock().unwrap();
+        state
+            .processor_durations
+            .entry(id.to_string())
+            .or_default()
+            .observe(elapsed);
+        *state
+            .processor_results
+            .entry((id.to_string(), result))
+            .or_default() += 1;
+    }
+
+    pub(crate) fn action_sent(&self, action: &str, echo: &str) {
+        self.in_flight
+            .insert(echo.to_string(), (action.to_string(), Instant::now()));
+    }
+
+    pub(crate) fn action_response(&self, response: &Response) {
+        let Some((_, (action, sent))) = self.in_flight.remove(&response.echo) else {
+            return;
+        };
+        let mut state = self.state.lock().unwrap();
+        state
+            .action_durations
+            .entry(action.clone())
+            .or_default()
+            .observe(sent.elapsed());
+        *state
+            .action_responses
+            .entry((action, response.retcode))
+            .or_default() += 1;
+    }
+
+    /// 连接断开时未收到响应的 action 不再统计
+    pub(crate) fn forget(&self, echo: &str) {
+        self.in_flight.remove(echo);
+    }
+
+    fn pending_echoes(&self) -> usize {
+        let mut echo_notifers = self.echo_notifers.lock().unwrap();
+        echo_notifers.retain(|echo_notifer| echo_notifer.strong_count() > 0);
+        echo_notifers
+            .iter()
+            .filter_map(|echo_notifer| echo_notifer.upgrade())
+            .map(|echo_notifer| echo_notifer.len())
+            .sum()
+    }
+
+    /// Prometheus 文本格式
+    pub fn render(&self) -> String {
+        let now = Instant::now();
+        self.in_flight
+            .retain(|_, (_, sent)| now.duration_since(*sent) < IN_FLIGHT_TTL);
+        let pending_echoes = self.pending_echoes();
+        let state = self.state.lock().unwrap();
+        let mut out = String::new();
+
+        out.push_str("# HELP runbot_events_total Posts received by post_type.\n");
+        out.push_str("# TYPE runbot_events_total counter\n");
+        for (post_type, count) in &state.events {
+            let _ = writeln!(
+                out,
+                "runbot_events_total{{post_type=\"{post_type}\"}} {count}"
+            );
+        }
+
+        out.push_str("# HELP runbot_parse_errors_total Frames that could not be parsed.\n");
+        out.push_str("# TYPE runbot_parse_errors_total counter\n");
+        let _ = writeln!(out, "runbot_parse_errors_total {}", state.parse_errors);
+
+        out.push_str("# HELP runbot_processor_duration_seconds Processor run time.\n");
+        out.push_str("# TYPE runbot_processor_duration_seconds histogram\n");
+        for (id, histogram) in &state.processor_durations {
+            histogram.render(
+                &mut out,
+                "runbot_processor_duration_seconds",
+                "processor",
+                id,
+            );
+        }
+
+        out.push_str(
+            "# HELP runbot_processor_results_total Processor results (handled, skipped, error).\n",
+        );
+        out.push_str("# TYPE runbot_processor_results_total counter\n");
+        for ((id, result), count) in &state.processor_results {
+            let _ = writeln!(
+                out,
+                "runbot_processor_results_total{{processor=\"{}\",result=\"{result}\"}} {count}",
+                escape(id)
+            );
+        }
+
+        out.push_str(
+            "# HELP runbot_action_duration_seconds Time from sending an action to its response.\n",
+        );
+        out.push_str("# TYPE runbot_action_duration_seconds histogram\n");
+        for (action, histogram) in &state.action_durations {
+            histogram.render(&mut out, "runbot_action_duration_seconds", "action", action);
+        }
+
+        out.push_str("# HELP runbot_action_responses_total Action responses by retcode.\n");
+        out.push_str("# TYPE runbot_action_responses_total counter\n");
+        for ((action, retcode), count) in &state.action_responses {
+            let _ = writeln!(
+                out,
+                "runbot_action_responses_total{{action=\"{}\",retcode=\"{retcode}\"}} {count}",
+                escape(action)
+            );
+        }
+
+        out.push_str("# HELP runbot_pending_echoes Actions waiting for a response.\n");
+        out.push_str("# TYPE runbot_pending_echoes gauge\n");
+        let _ = writeln!(out, "runbot_pending_echoes {pending_echoes}");
+        out
+    }
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// 在 `bind` 上提供 Prometheus 抓取, 任意路径都返回 `metrics.render()`
+pub async fn serve_metrics(metrics: Arc<Metrics>, bind: impl Into<String>) -> Result<()> {
+    let bind = bind.into();
+    let listener = TcpListener::bind(&bind).await?;
+    tracing::info!("Metrics server started on http://{}", &bind);
+    loop {
+        let (stream, addr) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                tracing::error!("Metrics accept error: {:?}", e);
+                continue;
+            }
+        };
+        let metrics = metrics.clone();
+        tokio::spawn(async move {
+            let service = service_fn(move |_request| {
+                let body = metrics.render();
+                async move {
+                    let mut response = hyper::Response::new(Full::new(Bytes::from(body)));
+                    response.headers_mut().insert(
+                        hyper::header::CONTENT_TYPE,
+                        hyper::header::HeaderValue::from_static("text/plain; version=0.0.4"),
+                    );
+                    Ok::<_, Infallible>(response)
+                }
+            });
+            if let Err(err) = http1::Builder::new()
+                .serve_connection(TokioIo::new(stream), service)
+                .await
+            {
+                tracing::error!("Metrics connection {} error: {:?}", addr, err);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(echo: &str, retcode: i64) -> Response {
+        Response {
+            status: "ok".to_string(),
+            retcode,
+            data: serde_json::Value::Null,
+            message: String::new(),
+            wording: String::new(),
+            echo: echo.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_render() {
+        let metrics = Metrics::new();
+        let echo_notifer: EchoNotifer = Arc::new(DashMap::new());
+        echo_notifer.insert("e2".to_string(), tokio::sync::mpsc::channel(1).0);
+        metrics.watch_echoes(&echo_notifer);
+        metrics.event(&Post::Response(response("e1", 0)));
+        metrics.processor("demo", Duration::from_millis(20), &Ok(true));
+        metrics.processor(
+            "demo",
+            Duration::from_secs(20),
+            &Err(anyhow::anyhow!("err")),
+        );
+        metrics.action_sent("send_group_msg", "e1");
+        metrics.action_response(&response("e1", 0));
+        metrics.action_response(&response("unknown", 0));
+
+        let text = metrics.render();
+        assert!(text.contains("runbot_events_total{post_type=\"response\"} 1\n"));
+        assert!(text.contains(
+            "runbot_processor_duration_seconds_bucket{processor=\"demo\",le=\"0.025\"} 1\n"
+        ));
+        assert!(text.contains(
+            "runbot_processor_duration_seconds_bucket{processor=\"demo\",le=\"+Inf\"} 2\n"
+        ));
+        assert!(
+            text.contains(
+                "runbot_processor_results_total{processor=\"demo\",result=\"error\"} 1\n"
+            )
+        );
+        assert!(
+            text.contains("runbot_action_duration_seconds_count{action=\"send_group_msg\"} 1\n")
+        );
+        assert!(text.contains(
+            "runbot_action_responses_total{action=\"send_group_msg\",retcode=\"0\"} 1\n"
+        ));
+        assert!(text.contains("runbot_pending_echoes 1\n"));
+        drop(echo_notifer);
+        assert!(metrics.render().contains("runbot_pending_echoes 0\n"));
+    }
+}
@@ -0,0 +1,382 @@
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::sync::{Arc, Mutex};
+
+use crate::bot_context::BotContext;
+use crate::event::Post;
+use crate::process::{Processor, run_processors};
+use async_trait::async_trait;
+use futures_util::future::BoxFuture;
+
+/// 包裹处理器链或单个处理器的中间件
+///
+/// 调用 `next.run` 继续处理, 可以传入修改后的 `Post`; 不调用则短路, 返回值作为处理结果。
+/// `next.run` 的返回值 (包括错误) 可以在返回前检查或替换。
+/// 需要传给内层的数据写入 `Extensions::current()`
+#[async_trait]
+pub trait Middleware: Send + Sync + Debug {
+    fn id(&self) -> &'static str;
+    async fn handle(
+        &self,
+        bot_ctx: Arc<BotContext>,
+        post: &Post,
+        next: Next<'_>,
+    ) -> anyhow::Result<bool>;
+}
+
+/// 一组中间件, 先添加的在外层
+///
+/// `chain` 包裹整个处理器链, `processor` 包裹其中的每个处理器
+#[derive(Debug, Clone, Default)]
+pub struct Middlewares {
+    pub chain: Vec<Arc<dyn Middleware>>,
+    pub processor: Vec<Arc<dyn Middleware>>,
+}
+
+impl Middlewares {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 包裹整个处理器链, 每个事件调用一次
+    ///
+    /// 中间件自身返回的错误不经过 `ErrorPolicy`, 需要时在中间件中处理;
+    /// 模块内的链中间件除外, 它的错误由外层按模块的错误处理
+    pub fn around_chain(mut self, middleware: impl Middleware + 'static) -> Self {
+        self.chain.push(Arc::new(middleware));
+        self
+    }
+
+    /// 包裹每个处理器, 每个事件的每个处理器调用一次
+    pub fn around_processor(mut self, middleware: impl Middleware + 'static) -> Self {
+        self.processor.push(Arc::new(middleware));
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chain.is_empty() && self.processor.is_empty()
+    }
+}
+
+tokio::task_local! {
+    static EXTENSIONS: Extensions;
+}
+
+/// 单个事件的扩展数据, 以类型区分
+///
+/// 中间件写入后, 内层的中间件与处理器通过 `Extensions::current()` 读取,
+/// 每个事件开始处理时为空; 处理器中 spawn 的任务无法获取
+#[derive(Clone, Default)]
+pub struct Extensions(Arc<Mutex<HashMap<TypeId, Box<dyn Any + Send + Sync>>>>);
+
+impl Extensions {
+    /// 当前事件的扩展数据, 不在处理事件时为 None
+    pub fn current() -> Option<Self> {
+        EXTENSIONS.try_with(|extensions| extensions.clone()).ok()
+    }
+
+    /// 写入数据, 返回同类型的旧值
+    pub fn insert<T: Send + Sync + 'static>(&self, value: T) -> Option<T> {
+        self.0
+            .lock()
+            .unwrap()
+            .insert(TypeId::of::<T>(), Box::new(value))
+            .and_then(|old| old.downcast().ok())
+            .map(|old| *old)
+    }
+
+    pub fn get<T: Clone + Send + Sync + 'static>(&self) -> Option<T> {
+        self.0
+            .lock()
+            .unwrap()
+            .get(&TypeId::of::<T>())
+            .and_then(|value| value.downcast_ref::<T>())
+            .cloned()
+    }
+
+    pub fn remove<T: Send + Sync + 'static>(&self) -> Option<T> {
+        self.0
+            .lock()
+            .unwrap()
+            .remove(&TypeId::of::<T>())
+            .and_then(|value| value.downcast().ok())
+            .map(|value| *value)
+    }
+
+    /// 在新的扩展数据中运行, 已经在处理事件时 (例如模块内的处理器链) 沿用外层的数据
+    pub(crate) async fn scope<F: Future>(future: F) -> F::Output {
+        if Self::current().is_some() {
+            future.await
+        } else {
+            EXTENSIONS.scope(Self::default(), future).await
+        }
+    }
+}
+
+impl Debug for Extensions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Extensions")
+            .field("len", &self.0.lock().unwrap().len())
+            .finish()
+    }
+}
+
+/// 剩余的中间件以及被包裹的处理器链或处理器
+pub struct Next<'a> {
+    middlewares: &'a [Arc<dyn Middleware>],
+    endpoint: Endpoint<'a>,
+}
+
+#[derive(Clone, Copy)]
+enum Endpoint<'a> {
+    Chain {
+        processors: &'a [Processor],
+        processor_middlewares: &'a [Arc<dyn Middleware>],
+    },
+    Processor(&'a Processor),
+}
+
+impl<'a> Next<'a> {
+    pub(crate) fn chain(processors: &'a [Processor], middlewares: &'a Middlewares) -> Self {
+        Self {
+            middlewares: &middlewares.chain,
+            endpoint: Endpoint::Chain {
+                processors,
+                processor_middlewares: &middlewares.processor,
+            },
+        }
+    }
+
+    pub(crate) fn processor(
+        processor: &'a Processor,
+        middlewares: &'a [Arc<dyn Middleware>],
+    ) -> Self {
+        Self {
+            middlewares,
+            endpoint: Endpoint::Processor(processor),
+        }
+    }
+
+    /// 被包裹的处理器, 包裹整个处理器链时为 None
+    pub fn current_processor(&self) -> Option<&'a Processor> {
+        match self.endpoint {
+            Endpoint::Processor(processor) => Some(processor),
+            Endpoint::Chain { .. } => None,
+        }
+    }
+
+    /// 调用下一个中间件, 没有时调用处理器链或处理器
+    pub fn run<'b>(
+        self,
+        bot_ctx: Arc<BotContext>,
+        post: &'b Post,
+    ) -> BoxFuture<'b, anyhow::Result<bool>>
+    where
+        'a: 'b,
+    {
+        Box::pin(async move {
+            match self.middlewares.split_first() {
+                Some((middleware, middlewares)) => {
+                    let next = Next {
+                        middlewares,
+                        endpoint: self.endpoint,
+                    };
+                    middleware.handle(bot_ctx, post, next).await
+                }
+                None => match self.endpoint {
+                    Endpoint::Chain {
+                        processors,
+                        processor_middlewares,
+                    } => run_processors(bot_ctx, processors, processor_middlewares, post).await,
+                    Endpoint::Processor(processor) => processor.process(bot_ctx, post).await,
+                },
+            }
+        })
+    }
+}
+
+impl Debug for Next<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Next")
+            .field("middlewares", &self.middlewares)
+            .field(
+                "processor",
+                &self.current_processor().map(|processor| processor.id()),
+            )
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bot_context::BotContextBuilder;
+    use crate::event::Response;
+    use crate::process::{PostProcessor, loop_processors};
+    use std::sync::Mutex;
+
+    #[derive(Debug)]
+    struct Record(&'static str, Arc<Mutex<Vec<String>>>);
+
+    #[async_trait]
+    impl PostProcessor for Record {
+        fn id(&self) -> &'static str {
+            self.0
+        }
+
+        async fn process_post(
+            &self,
+            _bot_ctx: Arc<BotContext>,
+            _post: &Post,
+        ) -> anyhow::Result<bool> {
+            self.1.lock().unwrap().push(self.0.to_string());
+            Ok(false)
+        }
+    }
+
+    #[derive(Debug)]
+    struct Around(&'static str, Arc<Mutex<Vec<String>>>);
+
+    #[async_trait]
+    impl Middleware for Around {
+        fn id(&self) -> &'static str {
+            self.0
+        }
+
+        async fn handle(
+            &self,
+            bot_ctx: Arc<BotContext>,
+            post: &Post,
+            next: Next<'_>,
+        ) -> anyhow::Result<bool> {
+            let target = next
+                .current_processor()
+                .map_or("chain", |processor| processor.id());
+            // 拦截处理器 b
+            if target == "b" {
+                return Ok(false);
+            }
+            self.1
+                .lock()
+                .unwrap()
+                .push(format!("{}>{}", self.0, target));
+            let result = next.run(bot_ctx, post).await;
+            self.1
+                .lock()
+                .unwrap()
+                .push(format!("{}<{}", self.0, target));
+            result
+        }
+    }
+
+    #[tokio::test]
+    async fn test_middlewares() {
+        let log = Arc::new(Mutex::new(vec![]));
+        let processors = vec![
+            Processor::Post(Box::new(Record("a", log.clone()))),
+            Processor::Post(Box::new(Record("b", log.clone()))),
+        ];
+        let middlewares = Middlewares::new()
+            .around_chain(Around("outer", log.clone()))
+            .around_chain(Around("inner", log.clone()))
+            .around_processor(Around("each", log.clone()));
+        let post = Post::Response(Response {
+            status: "ok".to_string(),
+            retcode: 0,
+            data: serde_json::Value::Null,
+            message: String::new(),
+            wording: String::new(),
+            echo: String::new(),
+        });
+        let bot_ctx = BotContextBuilder::new().build().unwrap();
+        let handled = loop_processors(bot_ctx, &processors, &middlewares, &post)
+            .await
+            .unwrap();
+        assert!(!handled);
+        assert_eq!(
+            *log.lock().unwrap(),
+            vec![
+                "outer>chain",
+                "inner>chain",
+                "each>a",
+                "a",
+                "each<a",
+                "inner<chain",
+                "outer<chain"
+            ]
+        );
+    }
+
+    #[derive(Debug, Clone, PartialEq)]
+    struct Caller(&'static str);
+
+    #[derive(Debug)]
+    struct Tag;
+
+    #[async_trait]
+    impl Middleware for Tag {
+        fn id(&self) -> &'static str {
+            "tag"
+        }
+
+        async fn handle(
+            &self,
+            bot_ctx: Arc<BotContext>,
+            post: &Post,
+            next: Next<'_>,
+        ) -> anyhow::Result<bool> {
+            let extensions = Extensions::current().unwrap();
+            if extensions.get::<Caller>().is_none() {
+                extensions.insert(Caller("tag"));
+            }
+            next.run(bot_ctx, post).await
+        }
+    }
+
+    #[derive(Debug)]
+    struct ReadCaller(Arc<Mutex<Vec<Option<Caller>>>>);
+
+    #[async_trait]
+    impl PostProcessor for ReadCaller {
+        fn id(&self) -> &'static str {
+            "read_caller"
+        }
+
+        async fn process_post(
+            &self,
+            _bot_ctx: Arc<BotContext>,
+            _post: &Post,
+        ) -> anyhow::Result<bool> {
+            let extensions = Extensions::current().unwrap();
+            self.0.lock().unwrap().push(extensions.get::<Caller>());
+            extensions.insert(Caller("processor"));
+            Ok(false)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_extensions() {
+        let seen = Arc::new(Mutex::new(vec![]));
+        let processors = vec![Processor::Post(Box::new(ReadCaller(seen.clone())))];
+        let post = Post::Response(Response {
+            status: "ok".to_string(),
+            retcode: 0,
+            data: serde_json::Value::Null,
+            message: String::new(),
+            wording: String::new(),
+            echo: String::new(),
+        });
+        let bot_ctx = BotContextBuilder::new().build().unwrap();
+        assert!(Extensions::current().is_none());
+        let tagged = Middlewares::new().around_chain(Tag);
+        loop_processors(bot_ctx.clone(), &processors, &tagged, &post)
+            .await
+            .unwrap();
+        // 每个事件的数据互不影响
+        loop_processors(bot_ctx, &processors, &Middlewares::new(), &post)
+            .await
+            .unwrap();
+        assert_eq!(*seen.lock().unwrap(), vec![Some(Caller("tag")), None]);
+    }
+}
@@ -1,5 +1,6 @@
 use async_trait::async_trait;
 
+use crate::middleware::Middlewares;
 use crate::process::Processor;
 
 #[async_trait]
@@ -11,4 +12,9 @@ pub trait Module {
     fn help() -> &'static str;
 
     fn processors() -> Vec<Processor>;
+
+    /// 只作用于模块内处理器的中间件, 可以在 `#[module]` 的 impl 中定义
+    fn middlewares() -> Middlewares {
+        Middlewares::default()
+    }
 }
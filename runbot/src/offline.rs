@@ -0,0 +1,201 @@
+use std::collections::{HashSet, VecDeque};
+use std::sync::Arc;
+
+use crate::event::Response;
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::{Duration, Instant};
+
+/// 离线缓冲设置
+///
+/// 连接断开期间, 指定的 action 不会立即失败, 而是暂存在有界队列中,
+/// 重新连接后按顺序发送, 原来的 `EchoAsyncResponse` 在发送并收到响应后完成。
+/// 超过 `ttl` 仍未发送的 action 以 `Error::ConnectionLost` 结束
+#[derive(Debug, Clone)]
+pub struct OfflineBuffer {
+    pub capacity: usize,
+    pub ttl: Duration,
+    pub actions: HashSet<String>,
+}
+
+impl Default for OfflineBuffer {
+    fn default() -> Self {
+        Self {
+            capacity: 100,
+            ttl: Duration::from_secs(60),
+            actions: ["send_private_msg", "send_group_msg"]
+                .into_iter()
+                .map(String::from)
+                .collect(),
+        }
+    }
+}
+
+impl OfflineBuffer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 最多缓冲的 action 数量, 默认 100, 已满时新的 action 返回 `Error::NotConnected`
+    pub fn capacity(self, capacity: usize) -> Self {
+        Self { capacity, ..self }
+    }
+
+    /// 缓冲的最长时间, 默认 60 秒
+    pub fn ttl(self, ttl: Duration) -> Self {
+        Self { ttl, ..self }
+    }
+
+    /// 追加需要缓冲的 action, 默认只缓冲 `send_private_msg` 与 `send_group_msg`
+    pub fn buffer_action(mut self, action: impl Into<String>) -> Self {
+        self.actions.insert(action.into());
+        self
+    }
+}
+
+/// 等待发送的 action, 发送时才注册 echo, 断线清理 echo 时不会影响缓冲中的 action
+#[derive(Debug)]
+pub(crate) struct OfflineAction {
+    pub(crate) action: String,
+    pub(crate) echo: String,
+    pub(crate) msg: String,
+    pub(crate) sender: Option<mpsc::Sender<Response>>,
+    pub(crate) sent: Option<oneshot::Sender<()>>,
+    pub(crate) expires: Instant,
+}
+
+#[derive(Debug)]
+pub(crate) struct OfflineQueue {
+    config: OfflineBuffer,
+    queue: std::sync::Mutex<VecDeque<OfflineAction>>,
+}
+
+impl OfflineQueue {
+    pub(crate) fn new(config: OfflineBuffer) -> Self {
+        Self {
+            config,
+            queue: std::sync::Mutex::new(VecDeque::new()),
+        }
+    }
+
+    pub(crate) fn is_buffered(&self, action: &str) -> bool {
+        self.config.actions.contains(action)
+    }
+
+    pub(crate) fn expires(&self) -> Instant {
+        Instant::now() + self.config.ttl
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.queue.lock().unwrap().len()
+    }
+
+    /// 加入队尾, 队列已满时返回该 action
+    pub(crate) fn push(&self, action: OfflineAction) -> Option<OfflineAction> {
+        let mut queue = self.queue.lock().unwrap();
+        Self::retain_unexpired(&mut queue);
+        if queue.len() >= self.config.capacity {
+            return Some(action);
+        }
+        queue.push_back(action);
+        None
+    }
+
+    /// 放回队首, 用于发送失败或出站队列中尚未发送的 action
+    pub(crate) fn push_front(&self, actions: impl DoubleEndedIterator<Item = OfflineAction>) {
+        let mut queue = self.queue.lock().unwrap();
+        for action in actions.rev() {
+            queue.push_front(action);
+        }
+        queue.truncate(self.config.capacity);
+    }
+
+    /// 取出下一个未过期的 action, 过期的 action 直接丢弃
+    pub(crate) fn pop(&self) -> Option<OfflineAction> {
+        let mut queue = self.queue.lock().unwrap();
+        let now = Instant::now();
+        while let Some(action) = queue.pop_front() {
+            if action.expires > now {
+                return Some(action);
+            }
+            tracing::warn!("offline action {} expired: {}", action.action, action.echo);
+        }
+        None
+    }
+
+    /// 到期后清理过期的 action, 释放其 `sent`, 等待发送结果的 `EchoAsyncResponse` 随之结束
+    pub(crate) fn prune_at(self: &Arc<Self>, expires: Instant) {
+        let queue = Arc::downgrade(self);
+        tokio::spawn(async move {
+            tokio::time::sleep_until(expires).await;
+            if let Some(queue) = queue.upgrade() {
+                Self::retain_unexpired(&mut queue.queue.lock().unwrap());
+            }
+        });
+    }
+
+    fn retain_unexpired(queue: &mut VecDeque<OfflineAction>) {
+        let now = Instant::now();
+        queue.retain(|action| {
+            if action.expires > now {
+                return true;
+            }
+            tracing::warn!("offline action {} expired: {}", action.action, action.echo);
+            false
+        });
+    }
+
+    pub(crate) fn clear(&self) {
+        self.queue.lock().unwrap().clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn action(echo: &str, expires: Instant) -> OfflineAction {
+        OfflineAction {
+            action: "send_group_msg".to_string(),
+            echo: echo.to_string(),
+            msg: String::new(),
+            sender: None,
+            sent: None,
+            expires,
+        }
+    }
+
+    #[test]
+    fn test_bounded_and_expired() {
+        let queue = OfflineQueue::new(OfflineBuffer::new().capacity(2));
+        let now = Instant::now();
+        let later = now + Duration::from_secs(60);
+        assert!(queue.push(action("expired", now)).is_none());
+        assert!(queue.push(action("a", later)).is_none());
+        // 入队时清理过期的 action
+        assert!(queue.push(action("b", later)).is_none());
+        assert!(queue.push(action("c", later)).is_some());
+        queue.push_front([action("z", later)].into_iter());
+        assert_eq!(queue.len(), 2);
+        assert_eq!(queue.pop().unwrap().echo, "z");
+        assert_eq!(queue.pop().unwrap().echo, "a");
+        assert!(queue.pop().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_prune_at() {
+        let queue = Arc::new(OfflineQueue::new(OfflineBuffer::new()));
+        let expires = Instant::now() + Duration::from_millis(20);
+        let (sent, pending) = oneshot::channel();
+        queue.push_front(
+            [OfflineAction {
+                sent: Some(sent),
+                ..action("a", expires)
+            }]
+            .into_iter(),
+        );
+        queue.prune_at(expires);
+        // 过期后 sent 被释放, 不再需要等待下一次 push / pop
+        assert!(pending.await.is_err());
+        assert_eq!(queue.len(), 0);
+    }
+}
@@ -0,0 +1,465 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::Arc;
+
+use crate::bot_context::{BotConnection, EchoNotifer};
+use crate::error::Error;
+use tokio::sync::{Mutex, Notify, oneshot};
+use tokio::time::{Duration, Instant};
+
+/// 令牌桶限速: `per` 时间内最多 `count` 次, 允许 `count` 次突发
+#[derive(Debug, Clone, Copy)]
+pub struct RateLimit {
+    pub count: u32,
+    pub per: Duration,
+}
+
+impl RateLimit {
+    pub fn new(count: u32, per: Duration) -> Self {
+        Self {
+            count: count.max(1),
+            per,
+        }
+    }
+
+    pub fn per_second(count: u32) -> Self {
+        Self::new(count, Duration::from_secs(1))
+    }
+
+    pub fn per_minute(count: u32) -> Self {
+        Self::new(count, Duration::from_secs(60))
+    }
+}
+
+/// 出站消息的调度策略
+///
+/// 消息类 action 进入队列, 按 全局 / 每个群 / 每个用户 的令牌桶限速发送,
+/// 不同群与用户之间轮流发送, 避免一个群的大量消息阻塞其他群。
+/// 其他 action (例如 `get_*`) 不经过队列直接发送
+#[derive(Debug, Clone)]
+pub struct OutboundPolicy {
+    pub global: Option<RateLimit>,
+    pub per_group: Option<RateLimit>,
+    pub per_user: Option<RateLimit>,
+    pub queued_actions: HashSet<String>,
+}
+
+impl Default for OutboundPolicy {
+    fn default() -> Self {
+        Self {
+            global: None,
+            per_group: None,
+            per_user: None,
+            queued_actions: [
+                "send_msg",
+                "send_private_msg",
+                "send_group_msg",
+                "send_forward_msg",
+                "send_private_forward_msg",
+                "send_group_forward_msg",
+                "forward_friend_single_msg",
+                "forward_group_single_msg",
+            ]
+            .into_iter()
+            .map(String::from)
+            .collect(),
+        }
+    }
+}
+
+impl OutboundPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 所有排队 action 共享的限速
+    pub fn global(self, global: RateLimit) -> Self {
+        Self {
+            global: Some(global),
+            ..self
+        }
+    }
+
+    /// 每个群单独的限速
+    pub fn per_group(self, per_group: RateLimit) -> Self {
+        Self {
+            per_group: Some(per_group),
+            ..self
+        }
+    }
+
+    /// 每个私聊用户单独的限速
+    pub fn per_user(self, per_user: RateLimit) -> Self {
+        Self {
+            per_user: Some(per_user),
+            ..self
+        }
+    }
+
+    /// 追加需要排队的 action
+    pub fn queue_action(mut self, action: impl Into<String>) -> Self {
+        self.queued_actions.insert(action.into());
+        self
+    }
+}
+
+/// 排队的目标, 同一目标的消息按顺序发送
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Target {
+    Group(i64),
+    User(i64),
+    Other,
+}
+
+impl Target {
+    fn of(params: &serde_json::Value) -> Self {
+        let id = |key: &str| {
+            params
+                .get(key)
+                .and_then(|v| v.as_i64())
+                .filter(|id| *id != 0)
+        };
+        if let Some(group_id) = id("group_id") {
+            Target::Group(group_id)
+        } else if let Some(user_id) = id("user_id") {
+            Target::User(user_id)
+        } else {
+            Target::Other
+        }
+    }
+
+    fn limit(self, policy: &OutboundPolicy) -> Option<&RateLimit> {
+        match self {
+            Target::Group(_) => policy.per_group.as_ref(),
+            Target::User(_) => policy.per_user.as_ref(),
+            Target::Other => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct TokenBucket {
+    tokens: f64,
+    last: Instant,
+}
+
+impl TokenBucket {
+    fn new(limit: &RateLimit, now: Instant) -> Self {
+        Self {
+            tokens: limit.count as f64,
+            last: now,
+        }
+    }
+
+    fn refill(&mut self, limit: &RateLimit, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        let rate = limit.count as f64 / limit.per.as_secs_f64().max(f64::EPSILON);
+        self.tokens = (self.tokens + elapsed * rate).min(limit.count as f64);
+        self.last = now;
+    }
+
+    /// 下一个令牌可用的时间, 已有令牌时为 None
+    fn ready_at(&mut self, limit: &RateLimit, now: Instant) -> Option<Instant> {
+        self.refill(limit, now);
+        if self.tokens >= 1.0 {
+            return None;
+        }
+        let rate = limit.count as f64 / limit.per.as_secs_f64().max(f64::EPSILON);
+        Some(now + Duration::from_secs_f64((1.0 - self.tokens) / rate))
+    }
+
+    fn take(&mut self) {
+        self.tokens -= 1.0;
+    }
+
+    /// 令牌已经回满, 与新建的桶等价
+    fn is_full(&mut self, limit: &RateLimit, now: Instant) -> bool {
+        self.refill(limit, now);
+        self.tokens >= limit.count as f64
+    }
+}
+
+// 清理空闲令牌桶的间隔
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug)]
+pub(crate) struct QueuedAction {
+    pub(crate) action: String,
+    pub(crate) echo: String,
+    pub(crate) msg: String,
+    pub(crate) sent: Option<oneshot::Sender<()>>,
+}
+
+#[derive(Debug, Default)]
+struct OutboundState {
+    queues: HashMap<Target, VecDeque<QueuedAction>>,
+    // 有待发送消息的目标, 轮流发送
+    order: VecDeque<Target>,
+    buckets: HashMap<Target, TokenBucket>,
+    global: Option<TokenBucket>,
+    pruned_at: Option<Instant>,
+    depth: usize,
+    running: bool,
+}
+
+enum Next {
+    Ready(QueuedAction),
+    Wait(Instant),
+    Empty,
+}
+
+impl OutboundState {
+    fn push(&mut self, target: Target, action: QueuedAction) {
+        let queue = self.queues.entry(target).or_default();
+        if queue.is_empty() {
+            self.order.push_back(target);
+        }
+        queue.push_back(action);
+        self.depth += 1;
+    }
+
+    fn next(&mut self, policy: &OutboundPolicy, now: Instant) -> Next {
+        if self
+            .pruned_at
+            .is_none_or(|pruned_at| now.saturating_duration_since(pruned_at) >= PRUNE_INTERVAL)
+        {
+            self.prune(policy, now);
+            self.pruned_at = Some(now);
+        }
+        if self.order.is_empty() {
+            return Next::Empty;
+        }
+        if let Some(global) = &policy.global {
+            let bucket = self
+                .global
+                .get_or_insert_with(|| TokenBucket::new(global, now));
+            if let Some(ready_at) = bucket.ready_at(global, now) {
+                return Next::Wait(ready_at);
+            }
+        }
+        let mut wait_until: Option<Instant> = None;
+        for _ in 0..self.order.len() {
+            let target = self.order.pop_front().unwrap();
+            if let Some(limit) = target.limit(policy) {
+                let bucket = self
+                    .buckets
+                    .entry(target)
+                    .or_insert_with(|| TokenBucket::new(limit, now));
+                if let Some(ready_at) = bucket.ready_at(limit, now) {
+                    wait_until = Some(wait_until.map_or(ready_at, |w| w.min(ready_at)));
+                    self.order.push_back(target);
+                    continue;
+                }
+                bucket.take();
+            }
+            if let Some(global) = self.global.as_mut() {
+                global.take();
+            }
+            let queue = self.queues.get_mut(&target).unwrap();
+            let action = queue.pop_front().unwrap();
+            if queue.is_empty() {
+                self.queues.remove(&target);
+            } else {
+                self.order.push_back(target);
+            }
+            self.depth -= 1;
+            return Next::Ready(action);
+        }
+        Next::Wait(wait_until.unwrap_or(now))
+    }
+
+    /// 移除没有待发送消息且已经回满的令牌桶, 避免发送过的每个群和用户都留下一个桶
+    fn prune(&mut self, policy: &OutboundPolicy, now: Instant) {
+        let queues = &self.queues;
+        self.buckets
+            .retain(|target, bucket| match target.limit(policy) {
+                Some(limit) => queues.contains_key(target) || !bucket.is_full(limit, now),
+                None => false,
+            });
+    }
+
+    /// 清空队列, 同一目标的 action 保持原来的顺序
+    fn clear(&mut self) -> Vec<QueuedAction> {
+        self.depth = 0;
+        self.order
+            .drain(..)
+            .filter_map(|target| self.queues.remove(&target))
+            .flatten()
+            .collect()
+    }
+}
+
+/// BotContext 的出站调度器
+#[derive(Debug)]
+pub(crate) struct Outbound {
+    policy: OutboundPolicy,
+    state: std::sync::Mutex<OutboundState>,
+    notify: Notify,
+}
+
+impl Outbound {
+    pub(crate) fn new(policy: OutboundPolicy) -> Self {
+        Self {
+            policy,
+            state: std::sync::Mutex::new(OutboundState::default()),
+            notify: Notify::new(),
+        }
+    }
+
+    pub(crate) fn is_queued(&self, action: &str) -> bool {
+        self.policy.queued_actions.contains(action)
+    }
+
+    pub(crate) fn depth(&self) -> usize {
+        self.state.lock().unwrap().depth
+    }
+
+    /// 加入队列, 没有正在运行的发送任务时启动一个, 队列清空后任务退出
+    pub(crate) fn enqueue(
+        self: &Arc<Self>,
+        params: &serde_json::Value,
+        action: QueuedAction,
+        connection: Arc<Mutex<Option<BotConnection>>>,
+        echo_notifer: EchoNotifer,
+    ) {
+        let start = {
+            let mut state = self.state.lock().unwrap();
+            state.push(Target::of(params), action);
+            !std::mem::replace(&mut state.running, true)
+        };
+        if start {
+            tokio::spawn(self.clone().run(connection, echo_notifer));
+        } else {
+            self.notify.notify_one();
+        }
+    }
+
+    /// 断开连接时丢弃队列, 返回被丢弃的 action
+    pub(crate) fn clear(&self) -> Vec<QueuedAction> {
+        self.state.lock().unwrap().clear()
+    }
+
+    async fn run(
+        self: Arc<Self>,
+        connection: Arc<Mutex<Option<BotConnection>>>,
+        echo_notifer: EchoNotifer,
+    ) {
+        loop {
+            let next = {
+                let mut state = self.state.lock().unwrap();
+                let next = state.next(&self.policy, Instant::now());
+                if let Next::Empty = next {
+                    state.running = false;
+                }
+                next
+            };
+            match next {
+                Next::Empty => return,
+                Next::Wait(ready_at) => {
+                    // 新的目标入队时可能可以提前发送
+                    let _ = tokio::time::timeout_at(ready_at, self.notify.notified()).await;
+                }
+                Next::Ready(QueuedAction {
+                    action,
+                    echo,
+                    msg,
+                    sent,
+                }) => {
+                    tracing::debug!("WS send: {}", msg);
+                    let mut connection_lock = connection.lock().await;
+                    let result = match connection_lock.as_mut() {
+                        Some(connection) => connection.send_raw(msg).await,
+                        None => Err(Error::NotConnected(action)),
+                    };
+                    match result {
+                        Ok(()) => {
+                            if let Some(sent) = sent {
+                                let _ = sent.send(());
+                            }
+                        }
+                        Err(err) => {
+                            tracing::warn!("queued action {} not sent: {:?}", echo, err);
+                            echo_notifer.remove(&echo);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn action(echo: &str) -> QueuedAction {
+        QueuedAction {
+            action: "send_group_msg".to_string(),
+            echo: echo.to_string(),
+            msg: String::new(),
+            sent: None,
+        }
+    }
+
+    fn ready(state: &mut OutboundState, policy: &OutboundPolicy, now: Instant) -> Option<String> {
+        match state.next(policy, now) {
+            Next::Ready(action) => Some(action.echo),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_target() {
+        assert_eq!(
+            Target::of(&json!({"group_id": 1, "user_id": 2})),
+            Target::Group(1)
+        );
+        assert_eq!(
+            Target::of(&json!({"group_id": 0, "user_id": 2})),
+            Target::User(2)
+        );
+        assert_eq!(Target::of(&json!({"message_id": 3})), Target::Other);
+    }
+
+    #[test]
+    fn test_fair_and_limited() {
+        let policy = OutboundPolicy::new().per_group(RateLimit::per_second(1));
+        let mut state = OutboundState::default();
+        state.push(Target::Group(1), action("a1"));
+        state.push(Target::Group(1), action("a2"));
+        state.push(Target::Group(2), action("b1"));
+        let now = Instant::now();
+        assert_eq!(ready(&mut state, &policy, now).as_deref(), Some("a1"));
+        assert_eq!(ready(&mut state, &policy, now).as_deref(), Some("b1"));
+        // 群 1 需要等待令牌
+        match state.next(&policy, now) {
+            Next::Wait(ready_at) => assert!(ready_at > now),
+            _ => panic!("expected wait"),
+        }
+        let later = now + Duration::from_secs(1);
+        assert_eq!(ready(&mut state, &policy, later).as_deref(), Some("a2"));
+        assert_eq!(state.depth, 0);
+        assert!(matches!(state.next(&policy, later), Next::Empty));
+    }
+
+    #[test]
+    fn test_prune_idle_buckets() {
+        let policy = OutboundPolicy::new().per_group(RateLimit::per_second(1));
+        let mut state = OutboundState::default();
+        let now = Instant::now();
+        for group_id in 1..=100 {
+            state.push(Target::Group(group_id), action("a"));
+        }
+        state.push(Target::Group(1), action("b"));
+        for _ in 0..100 {
+            assert!(ready(&mut state, &policy, now).is_some());
+        }
+        assert_eq!(state.buckets.len(), 100);
+
+        // 群 1 仍有待发送的消息, 其余群的桶回满后被清理
+        let later = now + PRUNE_INTERVAL;
+        assert_eq!(ready(&mut state, &policy, later).as_deref(), Some("b"));
+        assert_eq!(state.buckets.len(), 1);
+        assert!(state.buckets.contains_key(&Target::Group(1)));
+    }
+}
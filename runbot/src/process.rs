@@ -2,9 +2,12 @@ use std::{fmt::Debug, sync::Arc};
 
 use crate::{
     bot_context::BotContext,
+    error_policy::{ErrorAction, PolicyStopped, ProcessorError, UserError, reply_error},
     event::{self, Post},
+    middleware::{Extensions, Middleware, Middlewares, Next},
 };
 use async_trait::async_trait;
+use tracing::Instrument;
 
 #[derive(Debug)]
 pub enum Processor {
@@ -108,6 +111,31 @@ pub struct ProcessModule {
     pub name: &'static str,
     pub help: &'static str,
     pub processors: Arc<Vec<Processor>>,
+    // 只作用于模块内的处理器, 通过 `middlewares` 设置
+    middlewares: Arc<Middlewares>,
+}
+
+impl ProcessModule {
+    pub fn new(
+        id: &'static str,
+        name: &'static str,
+        help: &'static str,
+        processors: Vec<Processor>,
+    ) -> Self {
+        Self {
+            id,
+            name,
+            help,
+            processors: Arc::new(processors),
+            middlewares: Arc::new(Middlewares::new()),
+        }
+    }
+
+    /// 只作用于模块内的处理器的中间件
+    pub fn middlewares(mut self, middlewares: Middlewares) -> Self {
+        self.middlewares = Arc::new(middlewares);
+        self
+    }
 }
 
 #[async_trait]
@@ -126,7 +154,7 @@ impl ModuleProcessor for ProcessModule {
         bot_ctx: Arc<BotContext>,
         post: &event::Post,
     ) -> anyhow::Result<bool> {
-        loop_processors(bot_ctx, self.processors.iter(), post).await
+        loop_processors(bot_ctx, &self.processors, &self.middlewares, post).await
     }
     fn processors(&self) -> Arc<Vec<Processor>> {
         self.processors.clone()
@@ -163,34 +191,208 @@ impl Into<Processor> for Box<dyn ModuleProcessor> {
     }
 }
 
+/// 依次调用处理器, 外层包裹 `middlewares.chain`, 每个处理器包裹 `middlewares.processor`
 pub(crate) async fn loop_processors(
     bot_ctx: Arc<BotContext>,
-    processor_iter: core::slice::Iter<'_, Processor>,
+    processors: &[Processor],
+    middlewares: &Middlewares,
+    post: &Post,
+) -> anyhow::Result<bool> {
+    Extensions::scope(Next::chain(processors, middlewares).run(bot_ctx, post)).await
+}
+
+pub(crate) async fn run_processors(
+    bot_ctx: Arc<BotContext>,
+    processors: &[Processor],
+    middlewares: &[Arc<dyn Middleware>],
     post: &Post,
 ) -> anyhow::Result<bool> {
-    for processor in processor_iter {
-        let processe_result = processor.process(bot_ctx.clone(), post).await;
+    for processor in processors {
+        // 在事件的 span 下, 每个处理器一个子 span
+        let span = tracing::info_span!("processor", id = processor.id());
+        let start = tokio::time::Instant::now();
+        let processe_result = Next::processor(processor, middlewares)
+            .run(bot_ctx.clone(), post)
+            .instrument(span.clone())
+            .await;
+        if let Some(metrics) = &bot_ctx.metrics {
+            metrics.processor(processor.id(), start.elapsed(), &processe_result);
+        }
         match processe_result {
             Ok(b) => {
                 if b {
-                    tracing::debug!(
-                        "post processed, id : {:?} , post : {:?}",
-                        processor.id(),
-                        post
-                    );
+                    span.in_scope(|| tracing::debug!("post processed"));
                     return Ok(b);
                 }
             }
+            // 模块内已经按策略处理过的错误, 只需继续停止
+            Err(err) if err.downcast_ref::<PolicyStopped>().is_some() => return Err(err),
             Err(err) => {
-                tracing::error!(
-                    "processor error, id: {:?} , post {:?} error: {:?}",
-                    processor.id(),
+                let error = ProcessorError {
+                    processor_id: processor.id(),
                     post,
-                    err
-                );
-                return Err(err);
+                    error: &err,
+                };
+                // 用户错误是预期内的, 不作为错误记录
+                if err.chain().any(|e| e.is::<UserError>()) {
+                    span.in_scope(|| tracing::debug!("processor user error: {:#}", err));
+                } else {
+                    span.in_scope(|| tracing::error!("processor error: {:?}", err));
+                }
+                match bot_ctx.error_policy.decide(&error) {
+                    ErrorAction::Continue => {}
+                    ErrorAction::Stop => return Err(err.context(PolicyStopped)),
+                    ErrorAction::Reply(text) => {
+                        reply_error(&bot_ctx, post, text).instrument(span).await;
+                        return Ok(true);
+                    }
+                }
             }
         }
     }
     Ok(false)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bot_context::{BotConnection, BotContextBuilder, WsWriter};
+    use crate::error_policy::ErrorPolicy;
+    use crate::event::{Message, MessageType};
+    use std::sync::Mutex;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[derive(Debug)]
+    struct Fail;
+
+    #[async_trait]
+    impl PostProcessor for Fail {
+        fn id(&self) -> &'static str {
+            "fail"
+        }
+
+        async fn process_post(
+            &self,
+            _bot_ctx: Arc<BotContext>,
+            _post: &Post,
+        ) -> anyhow::Result<bool> {
+            Err(anyhow::anyhow!("boom"))
+        }
+    }
+
+    #[derive(Debug)]
+    struct Count(Arc<AtomicUsize>);
+
+    #[async_trait]
+    impl PostProcessor for Count {
+        fn id(&self) -> &'static str {
+            "count"
+        }
+
+        async fn process_post(
+            &self,
+            _bot_ctx: Arc<BotContext>,
+            _post: &Post,
+        ) -> anyhow::Result<bool> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            Ok(true)
+        }
+    }
+
+    struct Capture(Arc<Mutex<Vec<String>>>);
+
+    #[async_trait]
+    impl WsWriter for Capture {
+        async fn send_raw(&mut self, msg: String) -> crate::error::Result<()> {
+            self.0.lock().unwrap().push(msg);
+            Ok(())
+        }
+    }
+
+    fn group_message() -> Post {
+        Post::Message(Message {
+            message_type: MessageType::Group,
+            group_id: 1,
+            user_id: 2,
+            ..Default::default()
+        })
+    }
+
+    fn counting_policy(decided: Arc<AtomicUsize>, action: ErrorAction) -> ErrorPolicy {
+        ErrorPolicy::new(move |_| {
+            decided.fetch_add(1, Ordering::SeqCst);
+            action.clone()
+        })
+    }
+
+    #[tokio::test]
+    async fn test_continue_after_error() {
+        let decided = Arc::new(AtomicUsize::new(0));
+        let processed = Arc::new(AtomicUsize::new(0));
+        let bot_ctx = BotContextBuilder::new()
+            .error_policy(counting_policy(decided.clone(), ErrorAction::Continue))
+            .build()
+            .unwrap();
+        let processors = vec![
+            Processor::Post(Box::new(Fail)),
+            Processor::Post(Box::new(Count(processed.clone()))),
+        ];
+        let result = run_processors(bot_ctx, &processors, &[], &group_message()).await;
+        assert!(result.unwrap());
+        assert_eq!(decided.load(Ordering::SeqCst), 1);
+        assert_eq!(processed.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_reply_error() {
+        let sent = Arc::new(Mutex::new(vec![]));
+        let bot_ctx = BotContextBuilder::new()
+            .error_policy(ErrorPolicy::new(|_| {
+                ErrorAction::Reply("出错了".to_string())
+            }))
+            .build()
+            .unwrap();
+        bot_ctx
+            .set_connection(BotConnection::new(Box::new(Capture(sent.clone()))))
+            .await;
+        let processed = Arc::new(AtomicUsize::new(0));
+        let processors = vec![
+            Processor::Post(Box::new(Fail)),
+            Processor::Post(Box::new(Count(processed.clone()))),
+        ];
+        let result = run_processors(bot_ctx, &processors, &[], &group_message()).await;
+        assert!(result.unwrap());
+        assert_eq!(processed.load(Ordering::SeqCst), 0);
+        let sent = sent.lock().unwrap();
+        assert_eq!(sent.len(), 1);
+        let action: serde_json::Value = serde_json::from_str(&sent[0]).unwrap();
+        assert_eq!(action["action"], "send_group_msg");
+        assert_eq!(action["params"]["group_id"], 1);
+        assert!(sent[0].contains("出错了"));
+    }
+
+    #[tokio::test]
+    async fn test_module_error_decided_once() {
+        let decided = Arc::new(AtomicUsize::new(0));
+        let processed = Arc::new(AtomicUsize::new(0));
+        let bot_ctx = BotContextBuilder::new()
+            .error_policy(counting_policy(decided.clone(), ErrorAction::Stop))
+            .build()
+            .unwrap();
+        let module = ProcessModule::new(
+            "module",
+            "module",
+            "",
+            vec![Processor::Post(Box::new(Fail))],
+        );
+        let processors = vec![
+            Processor::Module(Box::new(module)),
+            Processor::Post(Box::new(Count(processed.clone()))),
+        ];
+        let result = run_processors(bot_ctx, &processors, &[], &group_message()).await;
+        let err = result.unwrap_err();
+        assert_eq!(err.root_cause().to_string(), "boom");
+        assert_eq!(decided.load(Ordering::SeqCst), 1);
+        assert_eq!(processed.load(Ordering::SeqCst), 0);
+    }
+}
@@ -0,0 +1,248 @@
+use std::net::IpAddr;
+
+use crate::error::{Error, Result};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+/// 正向ws 使用的代理
+///
+/// 先与代理建立隧道, 再在隧道上进行 TLS (`wss://`) 与 WebSocket 握手
+#[derive(Debug, Clone)]
+pub struct Proxy {
+    pub kind: ProxyKind,
+    pub addr: String,
+    pub auth: Option<(String, String)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProxyKind {
+    /// HTTP CONNECT
+    Http,
+    Socks5,
+}
+
+impl Proxy {
+    /// HTTP CONNECT 代理, `addr` 为 `host:port`
+    pub fn http(addr: impl Into<String>) -> Self {
+        Self {
+            kind: ProxyKind::Http,
+            addr: addr.into(),
+            auth: None,
+        }
+    }
+
+    /// SOCKS5 代理, `addr` 为 `host:port`
+    pub fn socks5(addr: impl Into<String>) -> Self {
+        Self {
+            kind: ProxyKind::Socks5,
+            addr: addr.into(),
+            auth: None,
+        }
+    }
+
+    /// 用户名与密码, HTTP 代理使用 basic 认证, SOCKS5 使用用户名密码认证
+    pub fn auth(self, username: impl Into<String>, password: impl Into<String>) -> Self {
+        Self {
+            auth: Some((username.into(), password.into())),
+            ..self
+        }
+    }
+
+    /// 连接代理并建立到 `host:port` 的隧道
+    pub(crate) async fn connect(&self, host: &str, port: u16) -> Result<TcpStream> {
+        let mut stream = TcpStream::connect(&self.addr).await?;
+        match self.kind {
+            ProxyKind::Http => http_connect(&mut stream, host, port, self.auth.as_ref()).await?,
+            ProxyKind::Socks5 => {
+                socks5_connect(&mut stream, host, port, self.auth.as_ref()).await?
+            }
+        }
+        Ok(stream)
+    }
+}
+
+async fn http_connect<S>(
+    stream: &mut S,
+    host: &str,
+    port: u16,
+    auth: Option<&(String, String)>,
+) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let target = match host.parse::<IpAddr>() {
+        Ok(IpAddr::V6(ip)) => format!("[{}]:{}", ip, port),
+        _ => format!("{}:{}", host, port),
+    };
+    let mut request = format!("CONNECT {target} HTTP/1.1\r\nHost: {target}\r\n");
+    if let Some((username, password)) = auth {
+        let credentials = STANDARD.encode(format!("{}:{}", username, password));
+        request.push_str(&format!("Proxy-Authorization: Basic {}\r\n", credentials));
+    }
+    request.push_str("\r\n");
+    stream.write_all(request.as_bytes()).await?;
+
+    // 逐字节读取响应头, 避免读走隧道中的数据
+    let mut response = Vec::new();
+    while !response.ends_with(b"\r\n\r\n") {
+        if response.len() > 8192 {
+            return Err(Error::ProxyError("response header too large".to_string()));
+        }
+        let byte = stream.read_u8().await?;
+        response.push(byte);
+    }
+    let response = String::from_utf8_lossy(&response);
+    let status_line = response.lines().next().unwrap_or_default();
+    let status = status_line.split_whitespace().nth(1).unwrap_or_default();
+    if status != "200" {
+        return Err(Error::ProxyError(format!(
+            "CONNECT {}: {}",
+            target, status_line
+        )));
+    }
+    Ok(())
+}
+
+async fn socks5_connect<S>(
+    stream: &mut S,
+    host: &str,
+    port: u16,
+    auth: Option<&(String, String)>,
+) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    // 0x00: 无需认证, 0x02: 用户名密码
+    let method = if auth.is_some() { 0x02 } else { 0x00 };
+    stream.write_all(&[0x05, 0x01, method]).await?;
+    let mut reply = [0u8; 2];
+    stream.read_exact(&mut reply).await?;
+    if reply[0] != 0x05 || reply[1] != method {
+        return Err(Error::ProxyError(format!(
+            "socks5 auth method not accepted: {:#04x}",
+            reply[1]
+        )));
+    }
+
+    if let Some((username, password)) = auth {
+        if username.len() > 255 || password.len() > 255 {
+            return Err(Error::ProxyError(
+                "socks5 username or password too long".to_string(),
+            ));
+        }
+        let mut request = vec![0x01, username.len() as u8];
+        request.extend_from_slice(username.as_bytes());
+        request.push(password.len() as u8);
+        request.extend_from_slice(password.as_bytes());
+        stream.write_all(&request).await?;
+        stream.read_exact(&mut reply).await?;
+        if reply[1] != 0x00 {
+            return Err(Error::ProxyError(
+                "socks5 authentication failed".to_string(),
+            ));
+        }
+    }
+
+    let mut request = vec![0x05, 0x01, 0x00];
+    match host.parse::<IpAddr>() {
+        Ok(IpAddr::V4(ip)) => {
+            request.push(0x01);
+            request.extend_from_slice(&ip.octets());
+        }
+        Ok(IpAddr::V6(ip)) => {
+            request.push(0x04);
+            request.extend_from_slice(&ip.octets());
+        }
+        Err(_) => {
+            if host.len() > 255 {
+                return Err(Error::ProxyError(format!("host too long: {}", host)));
+            }
+            request.push(0x03);
+            request.push(host.len() as u8);
+            request.extend_from_slice(host.as_bytes());
+        }
+    }
+    request.extend_from_slice(&port.to_be_bytes());
+    stream.write_all(&request).await?;
+
+    let mut reply = [0u8; 4];
+    stream.read_exact(&mut reply).await?;
+    if reply[1] != 0x00 {
+        return Err(Error::ProxyError(format!(
+            "socks5 connect {}:{} failed: {:#04x}",
+            host, port, reply[1]
+        )));
+    }
+    // 读取并丢弃代理绑定的地址与端口
+    let addr_len = match reply[3] {
+        0x01 => 4,
+        0x04 => 16,
+        0x03 => stream.read_u8().await? as usize,
+        atyp => {
+            return Err(Error::ProxyError(format!(
+                "socks5 unknown address type: {:#04x}",
+                atyp
+            )));
+        }
+    };
+    let mut bound = vec![0u8; addr_len + 2];
+    stream.read_exact(&mut bound).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_http_connect() {
+        let (mut client, mut server) = tokio::io::duplex(1024);
+        let auth = ("user".to_string(), "pass".to_string());
+        let proxy = tokio::spawn(async move {
+            let mut buf = vec![0u8; 1024];
+            let n = server.read(&mut buf).await.unwrap();
+            server
+                .write_all(b"HTTP/1.1 200 Connection established\r\n\r\nws")
+                .await
+                .unwrap();
+            String::from_utf8_lossy(&buf[..n]).to_string()
+        });
+        http_connect(&mut client, "example.com", 443, Some(&auth))
+            .await
+            .unwrap();
+        let request = proxy.await.unwrap();
+        assert!(request.starts_with("CONNECT example.com:443 HTTP/1.1\r\n"));
+        assert!(request.contains("Proxy-Authorization: Basic dXNlcjpwYXNz\r\n"));
+        // 隧道中的数据不会被读走
+        let mut rest = [0u8; 2];
+        client.read_exact(&mut rest).await.unwrap();
+        assert_eq!(&rest, b"ws");
+    }
+
+    #[tokio::test]
+    async fn test_socks5_connect() {
+        let (mut client, mut server) = tokio::io::duplex(1024);
+        let proxy = tokio::spawn(async move {
+            let mut greeting = [0u8; 3];
+            server.read_exact(&mut greeting).await.unwrap();
+            server.write_all(&[0x05, 0x00]).await.unwrap();
+            let mut request = [0u8; 5 + 11 + 2];
+            server.read_exact(&mut request).await.unwrap();
+            server
+                .write_all(&[0x05, 0x00, 0x00, 0x01, 127, 0, 0, 1, 0x1f, 0x90])
+                .await
+                .unwrap();
+            (greeting, request)
+        });
+        socks5_connect(&mut client, "example.com", 8080, None)
+            .await
+            .unwrap();
+        let (greeting, request) = proxy.await.unwrap();
+        assert_eq!(greeting, [0x05, 0x01, 0x00]);
+        assert_eq!(&request[..5], &[0x05, 0x01, 0x00, 0x03, 11]);
+        assert_eq!(&request[5..16], b"example.com");
+        assert_eq!(&request[16..], &8080u16.to_be_bytes());
+    }
+}
@@ -0,0 +1,214 @@
+use std::fmt::{self, Debug};
+use std::sync::Arc;
+
+use crate::error::Error;
+use tokio::time::Duration;
+
+/// loop_client 的重连策略
+///
+/// 第 n 次重连前等待 `initial_delay * multiplier^(n-1)`, 不超过 `max_delay`,
+/// 再叠加 `±jitter` 比例的随机抖动。默认值与旧版本一致: 固定 15 秒, 无限重连
+#[derive(Debug, Clone)]
+pub struct ReconnectPolicy {
+    pub initial_delay: Duration,
+    pub multiplier: f64,
+    pub max_delay: Duration,
+    pub jitter: f64,
+    pub max_attempts: Option<u32>,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self {
+            initial_delay: Duration::from_secs(15),
+            multiplier: 1.0,
+            max_delay: Duration::from_secs(15),
+            jitter: 0.0,
+            max_attempts: None,
+        }
+    }
+}
+
+impl ReconnectPolicy {
+    /// 固定间隔重连
+    pub fn fixed(delay: Duration) -> Self {
+        Self {
+            initial_delay: delay,
+            max_delay: delay,
+            ..Default::default()
+        }
+    }
+
+    /// 指数退避重连, 从 `initial_delay` 开始每次翻倍, 最多等待 `max_delay`
+    pub fn exponential(initial_delay: Duration, max_delay: Duration) -> Self {
+        Self {
+            initial_delay,
+            multiplier: 2.0,
+            max_delay,
+            ..Default::default()
+        }
+    }
+
+    pub fn multiplier(self, multiplier: f64) -> Self {
+        Self { multiplier, ..self }
+    }
+
+    /// 抖动比例, 取值 0.0 ~ 1.0, 例如 0.2 表示在计算出的等待时间上随机浮动 ±20%
+    pub fn jitter(self, jitter: f64) -> Self {
+        Self {
+            jitter: jitter.clamp(0.0, 1.0),
+            ..self
+        }
+    }
+
+    /// 连续重连失败的最大次数, 超过后 loop_client 返回错误
+    pub fn max_attempts(self, max_attempts: u32) -> Self {
+        Self {
+            max_attempts: Some(max_attempts),
+            ..self
+        }
+    }
+
+    /// 第 attempt 次 (从 1 开始) 重连前的等待时间
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let exponent = attempt.saturating_sub(1).min(i32::MAX as u32) as i32;
+        let max_secs = self.max_delay.as_secs_f64();
+        let secs = (self.initial_delay.as_secs_f64() * self.multiplier.max(0.0).powi(exponent))
+            .min(max_secs);
+        let secs = if self.jitter > 0.0 {
+            secs * (1.0 + rand::random_range(-self.jitter..=self.jitter))
+        } else {
+            secs
+        };
+        Duration::from_secs_f64(secs.clamp(0.0, max_secs.max(0.0)))
+    }
+
+    pub(crate) fn exhausted(&self, attempt: u32) -> bool {
+        self.max_attempts
+            .is_some_and(|max_attempts| attempt > max_attempts)
+    }
+}
+
+/// 一次重连尝试的信息, 交给 `on_reconnect` 回调用于监控告警
+#[derive(Debug)]
+pub struct ReconnectAttempt<'a> {
+    /// 连续第几次重连, 连接成功后重新从 1 开始
+    pub attempt: u32,
+    /// 本次重连的原因, 已建立的连接断开时为 None
+    pub error: Option<&'a Error>,
+    /// 重连前的等待时间, 达到 max_attempts 放弃重连时为 None
+    pub delay: Option<Duration>,
+}
+
+#[derive(Clone)]
+pub struct ReconnectObserver(pub(crate) Arc<dyn Fn(&ReconnectAttempt) + Send + Sync>);
+
+impl Debug for ReconnectObserver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("ReconnectObserver")
+            .field(&"Arc<dyn Fn(&ReconnectAttempt)>")
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bot_context::BotContextBuilder;
+    use crate::connection::loop_client;
+    use std::sync::Mutex;
+    use tokio::sync::Notify;
+
+    #[test]
+    fn test_default_delay() {
+        let policy = ReconnectPolicy::default();
+        assert_eq!(policy.delay(1), Duration::from_secs(15));
+        assert_eq!(policy.delay(100), Duration::from_secs(15));
+        assert!(!policy.exhausted(u32::MAX));
+    }
+
+    #[test]
+    fn test_exponential_delay() {
+        let policy = ReconnectPolicy::exponential(Duration::from_secs(1), Duration::from_secs(10))
+            .max_attempts(5);
+        assert_eq!(policy.delay(1), Duration::from_secs(1));
+        assert_eq!(policy.delay(2), Duration::from_secs(2));
+        assert_eq!(policy.delay(4), Duration::from_secs(8));
+        assert_eq!(policy.delay(5), Duration::from_secs(10));
+        assert!(!policy.exhausted(5));
+        assert!(policy.exhausted(6));
+    }
+
+    #[test]
+    fn test_jitter_delay() {
+        let policy = ReconnectPolicy::exponential(Duration::from_secs(4), Duration::from_secs(60))
+            .jitter(0.5);
+        for _ in 0..100 {
+            let delay = policy.delay(1);
+            assert!(delay >= Duration::from_secs(2) && delay <= Duration::from_secs(6));
+        }
+    }
+
+    /// 没有监听的地址, 连接立即失败
+    fn unreachable_url() -> String {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        format!("ws://{}", listener.local_addr().unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_max_attempts() {
+        let attempts = Arc::new(Mutex::new(vec![]));
+        let bot_ctx = {
+            let attempts = attempts.clone();
+            BotContextBuilder::new()
+                .url(unreachable_url())
+                .reconnect_policy(ReconnectPolicy::fixed(Duration::from_millis(10)).max_attempts(3))
+                .on_reconnect(move |attempt| {
+                    attempts.lock().unwrap().push((
+                        attempt.attempt,
+                        matches!(attempt.error, Some(Error::WebSocketError(_))),
+                        attempt.delay,
+                    ));
+                })
+                .build()
+                .unwrap()
+        };
+        let result = tokio::time::timeout(Duration::from_secs(5), loop_client(bot_ctx))
+            .await
+            .unwrap();
+        assert!(matches!(result, Err(Error::StateError(_))));
+        let delay = Some(Duration::from_millis(10));
+        assert_eq!(
+            *attempts.lock().unwrap(),
+            vec![
+                (1, true, delay),
+                (2, true, delay),
+                (3, true, delay),
+                // 超过 max_attempts, 不再等待
+                (4, true, None),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_during_reconnect_wait() {
+        let waiting = Arc::new(Notify::new());
+        let bot_ctx = {
+            let waiting = waiting.clone();
+            BotContextBuilder::new()
+                .url(unreachable_url())
+                .reconnect_policy(ReconnectPolicy::fixed(Duration::from_secs(60)))
+                .on_reconnect(move |_| waiting.notify_one())
+                .build()
+                .unwrap()
+        };
+        let running = tokio::spawn(loop_client(bot_ctx.clone()));
+        waiting.notified().await;
+        bot_ctx.shutdown().await.unwrap();
+        let result = tokio::time::timeout(Duration::from_secs(1), running)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(result.is_ok());
+    }
+}
@@ -0,0 +1,551 @@
+use std::collections::HashMap;
+use std::future::{Future, IntoFuture};
+use std::net::SocketAddr;
+use std::pin::{Pin, pin};
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::error::Result;
+use crate::event::{MessageData, Response, SendMessage};
+use futures_util::future::{Either, select};
+use futures_util::{SinkExt, StreamExt};
+use serde::Serialize;
+use serde_json::{Value, json};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, watch};
+use tokio::task::JoinHandle;
+use tokio::time::{Duration, Instant};
+use tokio_tungstenite::tungstenite::Message as WsMessage;
+
+/// 用于集成测试的 onebot 实现
+///
+/// 在本地启动正向ws, 推送 onebot 格式的事件, 记录 bot 发送的每一个 action,
+/// 并按 echo 回复预设或默认的 `Response`
+///
+/// ```no_run
+/// # use runbot::prelude::*;
+/// # use runbot::testing::MockOneBot;
+/// # async fn test() {
+/// let mock = MockOneBot::start().await.unwrap();
+/// let bot_ctx = BotContextBuilder::new().url(mock.url()).build().unwrap();
+/// tokio::spawn(loop_client(bot_ctx.clone()));
+/// mock.wait_connected().await;
+/// mock.push_group_message(123, 456, "hello");
+/// mock.expect_action("send_group_msg")
+///     .with_param("group_id", 123)
+///     .with_text("world")
+///     .await;
+/// # }
+/// ```
+pub struct MockOneBot {
+    addr: SocketAddr,
+    shared: Arc<Shared>,
+    task: JoinHandle<()>,
+}
+
+/// bot 发送的 action
+#[derive(Debug, Clone)]
+pub struct ActionFrame {
+    pub action: String,
+    pub params: Value,
+    pub echo: String,
+}
+
+impl ActionFrame {
+    /// params 中 `message` 的文字部分
+    pub fn text(&self) -> String {
+        message_text(&self.params)
+    }
+}
+
+#[derive(Default)]
+struct MockState {
+    // bool: 是否已经被 expect_action 匹配
+    actions: Vec<(ActionFrame, bool)>,
+    responses: HashMap<String, Response>,
+    connections: Vec<mpsc::UnboundedSender<String>>,
+}
+
+struct Shared {
+    self_id: i64,
+    state: Mutex<MockState>,
+    actions: watch::Sender<usize>,
+    connections: watch::Sender<usize>,
+    // 每次 disconnect 加一, 当前的连接随之断开
+    disconnects: watch::Sender<usize>,
+    message_id: AtomicI64,
+}
+
+impl MockOneBot {
+    /// 在随机端口启动, self_id 为 10000
+    pub async fn start() -> Result<Self> {
+        Self::start_with_self_id(10000).await
+    }
+
+    pub async fn start_with_self_id(self_id: i64) -> Result<Self> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let shared = Arc::new(Shared {
+            self_id,
+            state: Mutex::new(MockState::default()),
+            actions: watch::Sender::new(0),
+            connections: watch::Sender::new(0),
+            disconnects: watch::Sender::new(0),
+            message_id: AtomicI64::new(1),
+        });
+        let task = tokio::spawn(accept_loop(listener, shared.clone()));
+        Ok(Self { addr, shared, task })
+    }
+
+    /// 正向ws 地址, 传给 `BotContextBuilder::url`
+    pub fn url(&self) -> String {
+        format!("ws://{}", self.addr)
+    }
+
+    pub fn self_id(&self) -> i64 {
+        self.shared.self_id
+    }
+
+    /// 等待 bot 连接, 5 秒内没有连接时 panic
+    pub async fn wait_connected(&self) {
+        let mut connections = self.shared.connections.subscribe();
+        if tokio::time::timeout(
+            Duration::from_secs(5),
+            connections.wait_for(|count| *count > 0),
+        )
+        .await
+        .is_err()
+        {
+            panic!("bot not connected to {}", self.url());
+        }
+    }
+
+    /// 断开当前所有连接 (不发送 Close 帧), 之后仍然接受新的连接
+    pub fn disconnect(&self) {
+        self.shared.disconnects.send_modify(|count| *count += 1);
+    }
+
+    /// 推送一个 onebot 格式的事件
+    pub fn push_event(&self, event: impl Serialize) {
+        let text = serde_json::to_string(&event).expect("serialize event");
+        let state = self.shared.state.lock().unwrap();
+        for connection in &state.connections {
+            let _ = connection.send(text.clone());
+        }
+    }
+
+    /// 推送群消息事件, 返回 message_id
+    pub fn push_group_message(
+        &self,
+        group_id: i64,
+        user_id: i64,
+        message: impl SendMessage,
+    ) -> i64 {
+        let message_id = self.shared.next_message_id();
+        let chain = message.chain();
+        self.push_event(json!({
+            "self_id": self.shared.self_id,
+            "user_id": user_id,
+            "time": now(),
+            "message_id": message_id,
+            "message_seq": message_id,
+            "message_type": "group",
+            "sender": {"user_id": user_id, "nickname": user_id.to_string(), "card": "", "role": "member"},
+            "raw_message": chain_text(&chain),
+            "font": 14,
+            "sub_type": "normal",
+            "message": chain,
+            "message_format": "array",
+            "post_type": "message",
+            "group_id": group_id,
+        }));
+        message_id
+    }
+
+    /// 推送心跳元事件, interval 为毫秒
+    pub fn push_heartbeat(&self, interval: i64, online: bool) {
+        self.push_event(json!({
+            "time": now(),
+            "self_id": self.shared.self_id,
+            "post_type": "meta_event",
+            "meta_event_type": "heartbeat",
+            "status": {"online": online, "good": online},
+            "interval": interval,
+        }));
+    }
+
+    /// 推送好友私聊消息事件, 返回 message_id
+    pub fn push_private_message(&self, user_id: i64, message: impl SendMessage) -> i64 {
+        let message_id = self.shared.next_message_id();
+        let chain = message.chain();
+        self.push_event(json!({
+            "self_id": self.shared.self_id,
+            "user_id": user_id,
+            "time": now(),
+            "message_id": message_id,
+            "message_seq": message_id,
+            "message_type": "private",
+            "sender": {"user_id": user_id, "nickname": user_id.to_string()},
+            "raw_message": chain_text(&chain),
+            "font": 14,
+            "sub_type": "friend",
+            "message": chain,
+            "message_format": "array",
+            "post_type": "message",
+        }));
+        message_id
+    }
+
+    /// 之后的 `action` 都以 `data` 成功响应
+    pub fn respond(&self, action: impl Into<String>, data: impl Serialize) {
+        let data = serde_json::to_value(data).expect("serialize data");
+        self.respond_with(action, response("ok", 0, data, ""));
+    }
+
+    /// 之后的 `action` 都以 `retcode` 失败响应
+    pub fn respond_failed(
+        &self,
+        action: impl Into<String>,
+        retcode: i64,
+        message: impl Into<String>,
+    ) {
+        self.respond_with(
+            action,
+            response("failed", retcode, Value::Null, &message.into()),
+        );
+    }
+
+    /// 之后的 `action` 都以 `response` 响应, echo 会被替换为请求的 echo
+    pub fn respond_with(&self, action: impl Into<String>, response: Response) {
+        let mut state = self.shared.state.lock().unwrap();
+        state.responses.insert(action.into(), response);
+    }
+
+    /// 已收到的所有 action
+    pub fn actions(&self) -> Vec<ActionFrame> {
+        let state = self.shared.state.lock().unwrap();
+        state
+            .actions
+            .iter()
+            .map(|(frame, _)| frame.clone())
+            .collect()
+    }
+
+    /// 等待一个匹配的 action, 每个 action 只会被匹配一次, 超时 (默认 5 秒) 时 panic
+    pub fn expect_action(&self, action: impl Into<String>) -> ExpectAction {
+        ExpectAction {
+            shared: self.shared.clone(),
+            action: action.into(),
+            params: vec![],
+            text: None,
+            timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+impl Drop for MockOneBot {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// `MockOneBot::expect_action` 的匹配条件, `.await` 后返回匹配到的 action
+pub struct ExpectAction {
+    shared: Arc<Shared>,
+    action: String,
+    params: Vec<(String, Value)>,
+    text: Option<String>,
+    timeout: Duration,
+}
+
+impl ExpectAction {
+    /// params 中 `key` 的值等于 `value`
+    pub fn with_param(mut self, key: impl Into<String>, value: impl Serialize) -> Self {
+        let value = serde_json::to_value(value).expect("serialize param");
+        self.params.push((key.into(), value));
+        self
+    }
+
+    /// params 中 `message` 的文字部分等于 `text`
+    pub fn with_text(self, text: impl Into<String>) -> Self {
+        Self {
+            text: Some(text.into()),
+            ..self
+        }
+    }
+
+    pub fn timeout(self, timeout: Duration) -> Self {
+        Self { timeout, ..self }
+    }
+
+    fn matches(&self, frame: &ActionFrame) -> bool {
+        frame.action == self.action
+            && self
+                .params
+                .iter()
+                .all(|(key, value)| frame.params.get(key) == Some(value))
+            && self.text.as_ref().is_none_or(|text| frame.text() == *text)
+    }
+
+    fn take(&self) -> Option<ActionFrame> {
+        let mut state = self.shared.state.lock().unwrap();
+        let (frame, matched) = state
+            .actions
+            .iter_mut()
+            .find(|(frame, matched)| !*matched && self.matches(frame))?;
+        *matched = true;
+        Some(frame.clone())
+    }
+
+    async fn wait(self) -> ActionFrame {
+        let mut actions = self.shared.actions.subscribe();
+        let deadline = Instant::now() + self.timeout;
+        loop {
+            if let Some(frame) = self.take() {
+                return frame;
+            }
+            if tokio::time::timeout_at(deadline, actions.changed())
+                .await
+                .is_err()
+            {
+                let received = self
+                    .shared
+                    .state
+                    .lock()
+                    .unwrap()
+                    .actions
+                    .iter()
+                    .map(|(frame, _)| format!("{} {}", frame.action, frame.params))
+                    .collect::<Vec<_>>();
+                panic!(
+                    "expected action {} with {:?} text {:?} not received in {:?}, received: {:#?}",
+                    self.action, self.params, self.text, self.timeout, received
+                );
+            }
+        }
+    }
+}
+
+impl IntoFuture for ExpectAction {
+    type Output = ActionFrame;
+    type IntoFuture = Pin<Box<dyn Future<Output = ActionFrame> + Send>>;
+
+    fn into_future(self) -> Self::IntoFuture {
+        Box::pin(self.wait())
+    }
+}
+
+impl Shared {
+    fn next_message_id(&self) -> i64 {
+        self.message_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// 预设的响应, 没有预设时使用默认响应
+    fn response(&self, frame: &ActionFrame) -> Response {
+        let preset = self
+            .state
+            .lock()
+            .unwrap()
+            .responses
+            .get(&frame.action)
+            .cloned();
+        let mut response = preset.unwrap_or_else(|| {
+            let data = match frame.action.as_str() {
+                "get_login_info" => json!({"user_id": self.self_id, "nickname": "mock"}),
+                "send_msg"
+                | "send_private_msg"
+                | "send_group_msg"
+                | "send_forward_msg"
+                | "send_private_forward_msg"
+                | "send_group_forward_msg" => {
+                    json!({"message_id": self.next_message_id()})
+                }
+                _ => Value::Null,
+            };
+            response("ok", 0, data, "")
+        });
+        response.echo = frame.echo.clone();
+        response
+    }
+
+    fn record(&self, frame: ActionFrame) {
+        self.state.lock().unwrap().actions.push((frame, false));
+        self.actions.send_modify(|count| *count += 1);
+    }
+}
+
+async fn accept_loop(listener: TcpListener, shared: Arc<Shared>) {
+    while let Ok((stream, _)) = listener.accept().await {
+        tokio::spawn(serve(stream, shared.clone()));
+    }
+}
+
+async fn serve(stream: TcpStream, shared: Arc<Shared>) {
+    let ws_stream = match tokio_tungstenite::accept_async(stream).await {
+        Ok(ws_stream) => ws_stream,
+        Err(e) => {
+            tracing::warn!("mock onebot handshake error: {:?}", e);
+            return;
+        }
+    };
+    let mut disconnects = shared.disconnects.subscribe();
+    disconnects.mark_unchanged();
+    let (mut sink, mut stream) = ws_stream.split();
+    let (tx, mut rx) = mpsc::unbounded_channel::<String>();
+    let _ = tx.send(
+        json!({
+            "time": now(),
+            "self_id": shared.self_id,
+            "post_type": "meta_event",
+            "meta_event_type": "lifecycle",
+            "sub_type": "connect",
+        })
+        .to_string(),
+    );
+    shared.state.lock().unwrap().connections.push(tx.clone());
+    shared.connections.send_modify(|count| *count += 1);
+
+    let writer = tokio::spawn(async move {
+        while let Some(text) = rx.recv().await {
+            if sink.send(WsMessage::Text(text.into())).await.is_err() {
+                break;
+            }
+        }
+    });
+    loop {
+        let msg = match select(pin!(disconnects.changed()), stream.next()).await {
+            Either::Right((Some(Ok(msg)), _)) => msg,
+            _ => break,
+        };
+        let text = match msg {
+            WsMessage::Text(text) => text.to_string(),
+            WsMessage::Binary(bin) => String::from_utf8_lossy(&bin).to_string(),
+            WsMessage::Close(_) => break,
+            _ => continue,
+        };
+        let Ok(value) = serde_json::from_str::<Value>(&text) else {
+            tracing::warn!("mock onebot received invalid frame: {}", text);
+            continue;
+        };
+        let frame = ActionFrame {
+            action: value["action"].as_str().unwrap_or_default().to_string(),
+            params: value["params"].clone(),
+            echo: value["echo"].as_str().unwrap_or_default().to_string(),
+        };
+        let response = shared.response(&frame);
+        shared.record(frame);
+        let _ = tx.send(serde_json::to_string(&response).expect("serialize response"));
+    }
+    writer.abort();
+    drop(tx);
+    shared
+        .state
+        .lock()
+        .unwrap()
+        .connections
+        .retain(|connection| !connection.is_closed());
+    shared.connections.send_modify(|count| *count -= 1);
+}
+
+fn response(status: &str, retcode: i64, data: Value, message: &str) -> Response {
+    Response {
+        status: status.to_string(),
+        retcode,
+        data,
+        message: message.to_string(),
+        wording: message.to_string(),
+        echo: String::new(),
+    }
+}
+
+fn chain_text(chain: &[MessageData]) -> String {
+    chain
+        .iter()
+        .filter_map(|data| match data {
+            MessageData::Text(text) => Some(text.text.as_str()),
+            _ => None,
+        })
+        .collect()
+}
+
+fn message_text(params: &Value) -> String {
+    params["message"]
+        .as_array()
+        .map(|segments| {
+            segments
+                .iter()
+                .filter(|segment| segment["type"] == "text")
+                .filter_map(|segment| segment["data"]["text"].as_str())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn now() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bot_context::BotContextBuilder;
+    use crate::connection::loop_client;
+    use crate::event::Message;
+    use crate::process::{MessageProcessor, Processor};
+
+    #[derive(Debug)]
+    struct Echo;
+
+    #[async_trait::async_trait]
+    impl MessageProcessor for Echo {
+        fn id(&self) -> &'static str {
+            "echo"
+        }
+
+        async fn process_message(
+            &self,
+            bot_ctx: Arc<crate::bot_context::BotContext>,
+            message: &Message,
+        ) -> anyhow::Result<bool> {
+            let response = bot_ctx
+                .send_group_message(message.group_id, message.raw_message.clone())
+                .await?;
+            response.wait_response().await?;
+            Ok(true)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_mock_onebot() {
+        let mock = MockOneBot::start_with_self_id(42).await.unwrap();
+        let bot_ctx = BotContextBuilder::new()
+            .url(mock.url())
+            .add_processor(Processor::Message(Box::new(Echo)))
+            .build()
+            .unwrap();
+        tokio::spawn(loop_client(bot_ctx.clone()));
+        mock.wait_connected().await;
+
+        mock.push_group_message(1, 2, "hello");
+        let frame = mock
+            .expect_action("send_group_msg")
+            .with_param("group_id", 1)
+            .with_text("hello")
+            .await;
+        assert!(!frame.echo.is_empty());
+        assert_eq!(bot_ctx.id(), 42);
+
+        mock.respond("get_login_info", json!({"user_id": 42, "nickname": "bot"}));
+        assert_eq!(bot_ctx.get_login_info().await.unwrap().nickname, "bot");
+        mock.respond_failed("delete_msg", 100, "no such message");
+        let response = bot_ctx.delete_msg(1).await.unwrap();
+        assert!(response.ok(Duration::from_secs(5)).await.is_err());
+        mock.expect_action("delete_msg")
+            .with_param("message_id", 1)
+            .await;
+        bot_ctx.shutdown().await.unwrap();
+    }
+}
@@ -0,0 +1,221 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use crate::error::{Error, Result};
+use rustls::{ClientConfig, RootCertStore, ServerConfig};
+use rustls_pki_types::pem::PemObject;
+use rustls_pki_types::{CertificateDer, PrivateKeyDer};
+use tokio_rustls::TlsAcceptor;
+
+/// 正向ws 使用 `wss://` 时的 TLS 设置
+///
+/// 默认信任 webpki 内置根证书, 可以追加自签名 CA, 或提供客户端证书用于双向认证
+#[derive(Debug, Clone)]
+pub struct ClientTlsConfig {
+    pub ca_files: Vec<PathBuf>,
+    pub webpki_roots: bool,
+    pub client_cert: Option<(PathBuf, PathBuf)>,
+}
+
+impl Default for ClientTlsConfig {
+    fn default() -> Self {
+        Self {
+            ca_files: vec![],
+            webpki_roots: true,
+            client_cert: None,
+        }
+    }
+}
+
+impl ClientTlsConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 追加信任的 CA 证书 (PEM)
+    pub fn ca_file(mut self, ca_file: impl Into<PathBuf>) -> Self {
+        self.ca_files.push(ca_file.into());
+        self
+    }
+
+    /// 不信任 webpki 内置根证书, 只信任 `ca_file` 添加的证书
+    pub fn without_webpki_roots(mut self) -> Self {
+        self.webpki_roots = false;
+        self
+    }
+
+    /// 客户端证书链与私钥 (PEM)
+    pub fn client_cert(
+        mut self,
+        cert_file: impl Into<PathBuf>,
+        key_file: impl Into<PathBuf>,
+    ) -> Self {
+        self.client_cert = Some((cert_file.into(), key_file.into()));
+        self
+    }
+
+    pub(crate) fn build(&self) -> Result<Arc<ClientConfig>> {
+        let mut root_store = RootCertStore::empty();
+        if self.webpki_roots {
+            root_store.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+        }
+        for ca_file in &self.ca_files {
+            for cert in load_certs(ca_file)? {
+                root_store
+                    .add(cert)
+                    .map_err(|e| Error::TlsError(format!("{}: {}", ca_file.display(), e)))?;
+            }
+        }
+        let builder =
+            ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+                .with_safe_default_protocol_versions()
+                .map_err(|e| Error::TlsError(e.to_string()))?
+                .with_root_certificates(root_store);
+        let config = match &self.client_cert {
+            Some((cert_file, key_file)) => builder
+                .with_client_auth_cert(load_certs(cert_file)?, load_key(key_file)?)
+                .map_err(|e| Error::TlsError(e.to_string()))?,
+            None => builder.with_no_client_auth(),
+        };
+        Ok(Arc::new(config))
+    }
+}
+
+/// 反向ws 以 `wss://` 提供服务时使用的证书链与私钥 (PEM)
+#[derive(Debug, Clone)]
+pub struct ServerTlsConfig {
+    pub cert_file: PathBuf,
+    pub key_file: PathBuf,
+}
+
+impl ServerTlsConfig {
+    pub fn new(cert_file: impl Into<PathBuf>, key_file: impl Into<PathBuf>) -> Self {
+        Self {
+            cert_file: cert_file.into(),
+            key_file: key_file.into(),
+        }
+    }
+
+    pub(crate) fn build(&self) -> Result<TlsAcceptor> {
+        let config =
+            ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+                .with_safe_default_protocol_versions()
+                .map_err(|e| Error::TlsError(e.to_string()))?
+                .with_no_client_auth()
+                .with_single_cert(load_certs(&self.cert_file)?, load_key(&self.key_file)?)
+                .map_err(|e| Error::TlsError(e.to_string()))?;
+        Ok(TlsAcceptor::from(Arc::new(config)))
+    }
+}
+
+fn load_certs(path: &PathBuf) -> Result<Vec<CertificateDer<'static>>> {
+    let certs = CertificateDer::pem_file_iter(path)
+        .and_then(|iter| iter.collect::<std::result::Result<Vec<_>, _>>())
+        .map_err(|e| Error::TlsError(format!("{}: {}", path.display(), e)))?;
+    if certs.is_empty() {
+        return Err(Error::TlsError(format!(
+            "{}: no certificate found",
+            path.display()
+        )));
+    }
+    Ok(certs)
+}
+
+fn load_key(path: &PathBuf) -> Result<PrivateKeyDer<'static>> {
+    PrivateKeyDer::from_pem_file(path)
+        .map_err(|e| Error::TlsError(format!("{}: {}", path.display(), e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bot_context::{BotContextBuilder, BotServerBuilder, BotServerEvent};
+    use crate::connection::{loop_client, loop_server};
+    use crate::reconnect::ReconnectPolicy;
+    use tokio::time::Duration;
+
+    #[test]
+    fn test_missing_files() {
+        let result = ServerTlsConfig::new("not_exists.pem", "not_exists.key").build();
+        assert!(matches!(result, Err(Error::TlsError(_))));
+        let result = ClientTlsConfig::new().ca_file("not_exists.pem").build();
+        assert!(matches!(result, Err(Error::TlsError(_))));
+        assert!(ClientTlsConfig::new().build().is_ok());
+    }
+
+    /// 自签名 CA (ca.pem) 以及由它签发的 localhost / 127.0.0.1 证书, 有效期 100 年
+    fn testdata(name: &str) -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("testdata/tls")
+            .join(name)
+    }
+
+    #[tokio::test]
+    async fn test_wss_round_trip() {
+        let bind = {
+            let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            listener.local_addr().unwrap().to_string()
+        };
+        let bot_server = BotServerBuilder::new()
+            .bind(bind.clone())
+            .tls(ServerTlsConfig::new(
+                testdata("server.pem"),
+                testdata("server.key"),
+            ))
+            .build()
+            .unwrap();
+        let mut events = bot_server.subscribe();
+        tokio::spawn(loop_server(bot_server.clone()));
+        let url = format!("wss://localhost:{}", bind.rsplit(':').next().unwrap());
+
+        // 只信任 webpki 内置根证书时, 自签名的服务端证书校验失败
+        let errors = Arc::new(std::sync::Mutex::new(vec![]));
+        let untrusted = {
+            let errors = errors.clone();
+            BotContextBuilder::new()
+                .url(url.clone())
+                .tls(ClientTlsConfig::new())
+                .reconnect_policy(ReconnectPolicy::fixed(Duration::from_millis(10)).max_attempts(0))
+                .on_reconnect(move |attempt| {
+                    errors
+                        .lock()
+                        .unwrap()
+                        .push(attempt.error.map(|error| error.to_string()));
+                })
+                .build()
+                .unwrap()
+        };
+        // 服务端可能尚未开始监听, 此时为连接错误
+        let error = loop {
+            errors.lock().unwrap().clear();
+            assert!(loop_client(untrusted.clone()).await.is_err());
+            let error = errors.lock().unwrap()[0].clone().unwrap();
+            if !error.contains("refused") {
+                break error;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        };
+        assert!(error.to_lowercase().contains("certificate"), "{}", error);
+
+        // 通过 ca_files 信任自签名 CA 后连接成功
+        let bot_ctx = BotContextBuilder::new()
+            .url(url)
+            .tls(
+                ClientTlsConfig::new()
+                    .without_webpki_roots()
+                    .ca_file(testdata("ca.pem")),
+            )
+            .build()
+            .unwrap();
+        tokio::spawn(loop_client(bot_ctx.clone()));
+        let connected = tokio::time::timeout(Duration::from_secs(5), events.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(matches!(connected, BotServerEvent::Connected(_)));
+        assert_eq!(bot_server.bots().len(), 1);
+
+        bot_ctx.shutdown().await.unwrap();
+        bot_server.shutdown().await.unwrap();
+    }
+}
@@ -0,0 +1,444 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, mpsc};
+
+use crate::bot_context::{BotConnection, BotContextBuilder, EchoNotifer, WsWriter};
+use crate::error::{Error, Result};
+use crate::event::{Post, Response, parse_post};
+use async_trait::async_trait;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use tokio::time::Duration;
+
+/// 帧的方向
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    /// onebot 实现发送给 bot 的事件与响应
+    In,
+    /// bot 发送的 action
+    Out,
+}
+
+/// 流量记录文件中的一行
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrafficRecord {
+    /// 毫秒时间戳
+    pub time: i64,
+    pub direction: Direction,
+    pub bot_id: i64,
+    /// 原始的 JSON 文本
+    pub frame: String,
+}
+
+/// 把收发的每一帧追加写入 JSONL 文件, 通过 `BotContextBuilder::record_traffic` 启用
+///
+/// 文件在单独的线程中写入, 不阻塞收发; bot 发送的 action 在实际写出连接后才记录
+#[derive(Debug)]
+pub struct TrafficRecorder {
+    path: PathBuf,
+    writer: mpsc::Sender<WriterCommand>,
+}
+
+#[derive(Debug)]
+enum WriterCommand {
+    Line(String),
+    Flush(tokio::sync::oneshot::Sender<()>),
+}
+
+impl TrafficRecorder {
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)?;
+        let (writer, commands) = mpsc::channel();
+        let writer_path = path.clone();
+        std::thread::Builder::new()
+            .name("runbot-traffic".to_string())
+            .spawn(move || write_lines(file, &writer_path, commands))?;
+        Ok(Self { path, writer })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub(crate) fn record(&self, direction: Direction, bot_id: i64, frame: &str) {
+        let record = TrafficRecord {
+            time: now_millis(),
+            direction,
+            bot_id,
+            frame: frame.to_string(),
+        };
+        let mut line = serde_json::to_string(&record).unwrap();
+        line.push('\n');
+        let _ = self.writer.send(WriterCommand::Line(line));
+    }
+
+    /// 等待已记录的帧写入文件
+    pub async fn flush(&self) {
+        let (done, flushed) = tokio::sync::oneshot::channel();
+        if self.writer.send(WriterCommand::Flush(done)).is_ok() {
+            let _ = flushed.await;
+        }
+    }
+}
+
+/// 写入线程, 所有 `TrafficRecorder` 的 sender 释放后退出
+fn write_lines(mut file: std::fs::File, path: &Path, commands: mpsc::Receiver<WriterCommand>) {
+    for command in commands {
+        match command {
+            // 一次写入整行, 多个 bot 共用一个文件时不会交错
+            WriterCommand::Line(line) => {
+                if let Err(err) = file.write_all(line.as_bytes()) {
+                    tracing::warn!("record traffic to {} error: {:?}", path.display(), err);
+                }
+            }
+            WriterCommand::Flush(done) => {
+                let _ = file.flush();
+                let _ = done.send(());
+            }
+        }
+    }
+}
+
+/// 回放流量记录
+///
+/// 离线地把记录中收到的帧交给 `parse_post` 与处理器, bot 发送的 action 不会真正发送,
+/// 而是以记录中同名 action 的响应依次回复, 最后与记录中的 action 对比
+#[derive(Debug, Clone)]
+pub struct TrafficReplayer {
+    pub path: PathBuf,
+    pub speed: Option<f64>,
+    pub ignored_actions: HashSet<String>,
+}
+
+impl TrafficReplayer {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            speed: None,
+            ignored_actions: ["get_login_info".to_string()].into_iter().collect(),
+        }
+    }
+
+    /// 按记录的时间间隔回放, 2.0 为两倍速, 不设置时不等待
+    pub fn speed(self, speed: f64) -> Self {
+        Self {
+            speed: Some(speed).filter(|speed| *speed > 0.0),
+            ..self
+        }
+    }
+
+    /// 对比时忽略的 action, 默认忽略连接后自动发送的 `get_login_info`
+    pub fn ignore_action(mut self, action: impl Into<String>) -> Self {
+        self.ignored_actions.insert(action.into());
+        self
+    }
+
+    /// 使用 `bot_ctx_builder` 中的处理器回放
+    pub async fn replay(&self, bot_ctx_builder: BotContextBuilder) -> Result<ReplayReport> {
+        let text = tokio::fs::read_to_string(&self.path).await?;
+        let mut report = ReplayReport::default();
+        let mut records = vec![];
+        for (index, line) in text.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            match serde_json::from_str::<TrafficRecord>(line) {
+                Ok(record) => records.push((index + 1, record)),
+                Err(err) => report.parse_failures.push(ParseFailure {
+                    line: index + 1,
+                    error: format!("invalid record: {}", err),
+                    frame: line.to_string(),
+                }),
+            }
+        }
+
+        // 记录中的 action 与对应的响应
+        let mut expected = vec![];
+        let mut responses_by_echo = HashMap::new();
+        for (_, record) in &records {
+            match record.direction {
+                Direction::Out => {
+                    if let Ok(frame) = serde_json::from_str::<Value>(&record.frame) {
+                        expected.push(frame);
+                    }
+                }
+                Direction::In => {
+                    if let Ok(Post::Response(response)) = parse_post(&record.frame) {
+                        responses_by_echo.insert(response.echo.clone(), response);
+                    }
+                }
+            }
+        }
+        let mut responses: HashMap<String, VecDeque<Response>> = HashMap::new();
+        for frame in &expected {
+            let action = frame["action"].as_str().unwrap_or_default();
+            let echo = frame["echo"].as_str().unwrap_or_default();
+            if let Some(response) = responses_by_echo.remove(echo) {
+                responses
+                    .entry(action.to_string())
+                    .or_default()
+                    .push_back(response);
+            }
+        }
+
+        let bot_ctx = bot_ctx_builder.build()?;
+        let actions = Arc::new(Mutex::new(vec![]));
+        bot_ctx
+            .set_connection(BotConnection::new(Box::new(ReplayWriter {
+                actions: actions.clone(),
+                responses,
+                echo_notifer: bot_ctx.echo_notifer.clone(),
+            })))
+            .await;
+
+        let mut last_time: Option<i64> = None;
+        for (line, record) in records {
+            if record.direction != Direction::In {
+                continue;
+            }
+            if let (Some(speed), Some(last_time)) = (self.speed, last_time) {
+                let elapsed = (record.time - last_time).max(0) as f64 / speed;
+                tokio::time::sleep(Duration::from_secs_f64(elapsed / 1000.0)).await;
+            }
+            last_time = Some(record.time);
+            match parse_post(&record.frame) {
+                Ok(Post::Response(_)) => {}
+                Ok(_) => {
+                    report.events += 1;
+                    bot_ctx.handle_text(bot_ctx.clone(), &record.frame).await;
+                }
+                Err(err) => report.parse_failures.push(ParseFailure {
+                    line,
+                    error: err.to_string(),
+                    frame: record.frame,
+                }),
+            }
+        }
+        bot_ctx.shutdown().await?;
+
+        let ignored = |frame: &Value| {
+            self.ignored_actions
+                .contains(frame["action"].as_str().unwrap_or_default())
+        };
+        let strip = |frame: &Value| json!({"action": frame["action"], "params": frame["params"]});
+        let expected: Vec<Value> = expected.iter().filter(|f| !ignored(f)).map(strip).collect();
+        report.actions = actions
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|f| !ignored(f))
+            .map(strip)
+            .collect();
+        for index in 0..expected.len().max(report.actions.len()) {
+            let expected = expected.get(index).cloned();
+            let actual = report.actions.get(index).cloned();
+            if expected != actual {
+                report.action_diffs.push(ActionDiff {
+                    index,
+                    expected,
+                    actual,
+                });
+            }
+        }
+        Ok(report)
+    }
+}
+
+/// 回放的结果
+#[derive(Debug, Clone, Default)]
+pub struct ReplayReport {
+    /// 交给处理器的事件数量
+    pub events: usize,
+    pub parse_failures: Vec<ParseFailure>,
+    /// 回放时处理器发送的 action (`{"action", "params"}`)
+    pub actions: Vec<Value>,
+    pub action_diffs: Vec<ActionDiff>,
+}
+
+impl ReplayReport {
+    pub fn is_ok(&self) -> bool {
+        self.parse_failures.is_empty() && self.action_diffs.is_empty()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ParseFailure {
+    /// 记录文件中的行号, 从 1 开始
+    pub line: usize,
+    pub error: String,
+    pub frame: String,
+}
+
+/// 第 `index` 个 action 与记录不同, 缺少或多出时对应一侧为 None
+#[derive(Debug, Clone)]
+pub struct ActionDiff {
+    pub index: usize,
+    pub expected: Option<Value>,
+    pub actual: Option<Value>,
+}
+
+/// 回放时代替连接, 记录 action 并回复记录中的响应
+struct ReplayWriter {
+    actions: Arc<Mutex<Vec<Value>>>,
+    responses: HashMap<String, VecDeque<Response>>,
+    echo_notifer: EchoNotifer,
+}
+
+#[async_trait]
+impl WsWriter for ReplayWriter {
+    async fn send_raw(&mut self, msg: String) -> Result<()> {
+        let frame: Value = serde_json::from_str(&msg)?;
+        let action = frame["action"]
+            .as_str()
+            .ok_or(Error::FieldError("action not found".to_string()))?
+            .to_string();
+        let echo = frame["echo"].as_str().unwrap_or_default().to_string();
+        self.actions.lock().unwrap().push(frame);
+        let mut response = self
+            .responses
+            .get_mut(&action)
+            .and_then(|responses| responses.pop_front())
+            .unwrap_or_else(|| Response {
+                status: "ok".to_string(),
+                retcode: 0,
+                data: Value::Null,
+                message: String::new(),
+                wording: String::new(),
+                echo: String::new(),
+            });
+        response.echo = echo.clone();
+        if let Some((_, sender)) = self.echo_notifer.remove(&echo) {
+            let _ = sender.try_send(response);
+        }
+        Ok(())
+    }
+}
+
+fn now_millis() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis() as i64)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bot_context::BotContext;
+    use crate::event::Message;
+    use crate::process::{MessageProcessor, Processor};
+
+    #[derive(Debug)]
+    struct Echo;
+
+    #[async_trait]
+    impl MessageProcessor for Echo {
+        fn id(&self) -> &'static str {
+            "echo"
+        }
+
+        async fn process_message(
+            &self,
+            bot_ctx: Arc<BotContext>,
+            message: &Message,
+        ) -> anyhow::Result<bool> {
+            let response = bot_ctx
+                .send_group_message(message.group_id, message.raw_message.clone())
+                .await?;
+            assert_eq!(response.wait_response().await?.message_id, 7);
+            Ok(true)
+        }
+    }
+
+    fn group_message(text: &str) -> String {
+        json!({
+            "self_id": 1, "user_id": 2, "time": 0, "message_id": 3, "message_seq": 3,
+            "message_type": "group", "sender": {"user_id": 2}, "raw_message": text,
+            "font": 14, "sub_type": "normal", "message_format": "array",
+            "message": [{"type": "text", "data": {"text": text}}],
+            "post_type": "message", "group_id": 100,
+        })
+        .to_string()
+    }
+
+    fn send_group_msg(text: &str, echo: &str) -> String {
+        json!({
+            "action": "send_group_msg",
+            "params": {"group_id": 100, "message": [{"type": "text", "data": {"text": text}}]},
+            "echo": echo,
+        })
+        .to_string()
+    }
+
+    #[tokio::test]
+    async fn test_record_and_replay() {
+        let path = std::env::temp_dir().join(format!("runbot-{}.jsonl", uuid::Uuid::new_v4()));
+        let recorder = TrafficRecorder::open(&path).unwrap();
+        let response = json!({
+            "status": "ok", "retcode": 0, "data": {"message_id": 7},
+            "message": "", "wording": "", "echo": "e1",
+        });
+        recorder.record(Direction::In, 1, &group_message("hello"));
+        recorder.record(Direction::Out, 1, &send_group_msg("hello", "e1"));
+        recorder.record(Direction::In, 1, &response.to_string());
+        recorder.record(Direction::In, 1, &group_message("again"));
+        recorder.record(Direction::Out, 1, &send_group_msg("changed", "e2"));
+        recorder.record(Direction::In, 1, r#"{"post_type": "message"}"#);
+        recorder.flush().await;
+
+        let report = TrafficReplayer::new(&path)
+            .replay(BotContextBuilder::new().add_processor(Processor::Message(Box::new(Echo))))
+            .await
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(report.events, 2);
+        assert_eq!(report.actions.len(), 2);
+        assert_eq!(report.parse_failures.len(), 1);
+        assert_eq!(report.parse_failures[0].line, 6);
+        assert_eq!(report.action_diffs.len(), 1);
+        assert_eq!(report.action_diffs[0].index, 1);
+        assert!(!report.is_ok());
+    }
+
+    struct NullWriter;
+
+    #[async_trait]
+    impl WsWriter for NullWriter {
+        async fn send_raw(&mut self, _msg: String) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_record_sent_actions_only() {
+        let path = std::env::temp_dir().join(format!("runbot-{}.jsonl", uuid::Uuid::new_v4()));
+        let bot_ctx = BotContextBuilder::new()
+            .record_traffic(&path)
+            .build()
+            .unwrap();
+        // 未连接时返回 NotConnected, 不记录
+        assert!(bot_ctx.websocket_send("get_status", json!({})).await.is_err());
+        bot_ctx
+            .set_connection(BotConnection::new(Box::new(NullWriter)))
+            .await;
+        let _ = bot_ctx.websocket_send("get_version_info", json!({})).await.unwrap();
+        bot_ctx.recorder.as_ref().unwrap().flush().await;
+
+        let text = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let records: Vec<TrafficRecord> = text
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].direction, Direction::Out);
+        assert!(records[0].frame.contains("get_version_info"));
+    }
+}
@@ -0,0 +1,224 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use runbot::prelude::*;
+use serde_json::{Value, json};
+
+#[processor(group_only, groups = [10], users = [20], at_me)]
+async fn group_at_me(_bot_ctx: Arc<BotContext>, _message: &Message) -> Result<bool> {
+    Ok(true)
+}
+
+#[processor(private_only, sub_type = "friend")]
+async fn friend_only(_bot_ctx: Arc<BotContext>, _message: &Message) -> Result<bool> {
+    Ok(true)
+}
+
+#[processor(command = "[/]ping", groups = [10])]
+async fn group_ping(_bot_ctx: Arc<BotContext>, message: &Message) -> Result<bool> {
+    Ok(message.group_id == 10)
+}
+
+#[processor(groups = [10], sub_type = "approve")]
+async fn group_approve(_bot_ctx: Arc<BotContext>, _notice: &Notice) -> Result<bool> {
+    Ok(true)
+}
+
+#[processor(group_only, users = [20])]
+async fn group_notice_of_user(_bot_ctx: Arc<BotContext>, _notice: &Notice) -> Result<bool> {
+    Ok(true)
+}
+
+#[processor(group_only, sub_type = "invite")]
+async fn group_invite(_bot_ctx: Arc<BotContext>, _request: &Request) -> Result<bool> {
+    Ok(true)
+}
+
+#[processor(users = [20])]
+async fn request_of_user(_bot_ctx: Arc<BotContext>, _request: &Request) -> Result<bool> {
+    Ok(true)
+}
+
+fn post(event: Value) -> Post {
+    parse_post(&event.to_string()).unwrap()
+}
+
+fn message(message_type: &str, sub_type: &str, group_id: i64, user_id: i64, text: &str) -> Message {
+    let mut chain = vec![json!({"type": "text", "data": {"text": text}})];
+    if let Some(qq) = text.strip_prefix("@") {
+        chain = vec![json!({"type": "at", "data": {"qq": qq}})];
+    }
+    let mut event = json!({
+        "self_id": 1,
+        "user_id": user_id,
+        "time": 0,
+        "message_id": 1,
+        "message_seq": 1,
+        "message_type": message_type,
+        "sender": {"user_id": user_id, "nickname": "", "card": "", "role": "member"},
+        "raw_message": text,
+        "font": 14,
+        "sub_type": sub_type,
+        "message": chain,
+        "message_format": "array",
+        "post_type": "message",
+    });
+    if group_id != 0 {
+        event["group_id"] = json!(group_id);
+    }
+    match post(event) {
+        Post::Message(message) => message,
+        post => panic!("expected message: {:?}", post),
+    }
+}
+
+fn notice(event: Value) -> Notice {
+    match post(event) {
+        Post::Notice(notice) => notice,
+        post => panic!("expected notice: {:?}", post),
+    }
+}
+
+fn group_increase(group_id: i64, user_id: i64, sub_type: &str) -> Notice {
+    notice(json!({
+        "post_type": "notice", "notice_type": "group_increase", "sub_type": sub_type,
+        "time": 0, "self_id": 1, "group_id": group_id, "operator_id": 0, "user_id": user_id,
+    }))
+}
+
+fn friend_add(user_id: i64) -> Notice {
+    notice(json!({
+        "post_type": "notice", "notice_type": "friend_add",
+        "time": 0, "self_id": 1, "user_id": user_id,
+    }))
+}
+
+fn request(event: Value) -> Request {
+    match post(event) {
+        Post::Request(request) => request,
+        post => panic!("expected request: {:?}", post),
+    }
+}
+
+fn group_request(group_id: i64, user_id: i64, sub_type: &str) -> Request {
+    request(json!({
+        "post_type": "request", "request_type": "group", "sub_type": sub_type,
+        "time": 0, "self_id": 1, "group_id": group_id, "user_id": user_id,
+        "comment": "", "flag": "f",
+    }))
+}
+
+fn friend_request(user_id: i64) -> Request {
+    request(json!({
+        "post_type": "request", "request_type": "friend",
+        "time": 0, "self_id": 1, "user_id": user_id, "comment": "", "flag": "f",
+    }))
+}
+
+#[tokio::test]
+async fn test_message_filters() {
+    let bot_ctx = BotContextBuilder::new().build().unwrap();
+    let cases = [
+        (message("group", "normal", 10, 20, "@1"), true),
+        (message("group", "normal", 11, 20, "@1"), false),
+        (message("group", "normal", 10, 21, "@1"), false),
+        (message("group", "normal", 10, 20, "@2"), false),
+        (message("group", "normal", 10, 20, "hello"), false),
+        (message("private", "friend", 0, 20, "@1"), false),
+    ];
+    for (message, expected) in cases {
+        let handled = GROUP_AT_ME
+            .process_message(bot_ctx.clone(), &message)
+            .await
+            .unwrap();
+        assert_eq!(handled, expected, "{:?}", message);
+    }
+
+    let cases = [
+        (message("private", "friend", 0, 20, "hi"), true),
+        (message("private", "normal", 0, 20, "hi"), false),
+        (message("group", "normal", 10, 20, "hi"), false),
+    ];
+    for (message, expected) in cases {
+        let handled = FRIEND_ONLY
+            .process_message(bot_ctx.clone(), &message)
+            .await
+            .unwrap();
+        assert_eq!(handled, expected, "{:?}", message);
+    }
+
+    // 过滤条件与命令同时生效
+    let cases = [
+        (message("group", "normal", 10, 20, "/ping"), true),
+        (message("group", "normal", 11, 20, "/ping"), false),
+        (message("group", "normal", 10, 20, "/pong"), false),
+    ];
+    for (message, expected) in cases {
+        let handled = GROUP_PING
+            .process_message(bot_ctx.clone(), &message)
+            .await
+            .unwrap();
+        assert_eq!(handled, expected, "{:?}", message);
+    }
+}
+
+#[tokio::test]
+async fn test_notice_filters() {
+    let bot_ctx = BotContextBuilder::new().build().unwrap();
+    let cases = [
+        (group_increase(10, 20, "approve"), true),
+        (group_increase(10, 20, "invite"), false),
+        (group_increase(11, 20, "approve"), false),
+        (friend_add(20), false),
+    ];
+    for (notice, expected) in cases {
+        let handled = GROUP_APPROVE
+            .process_notice(bot_ctx.clone(), &notice)
+            .await
+            .unwrap();
+        assert_eq!(handled, expected, "{:?}", notice);
+    }
+
+    let cases = [
+        (group_increase(10, 20, "invite"), true),
+        (group_increase(10, 21, "invite"), false),
+        (friend_add(20), false),
+    ];
+    for (notice, expected) in cases {
+        let handled = GROUP_NOTICE_OF_USER
+            .process_notice(bot_ctx.clone(), &notice)
+            .await
+            .unwrap();
+        assert_eq!(handled, expected, "{:?}", notice);
+    }
+}
+
+#[tokio::test]
+async fn test_request_filters() {
+    let bot_ctx = BotContextBuilder::new().build().unwrap();
+    let cases = [
+        (group_request(10, 20, "invite"), true),
+        (group_request(10, 20, "add"), false),
+        (friend_request(20), false),
+    ];
+    for (request, expected) in cases {
+        let handled = GROUP_INVITE
+            .process_request(bot_ctx.clone(), &request)
+            .await
+            .unwrap();
+        assert_eq!(handled, expected, "{:?}", request);
+    }
+
+    let cases = [
+        (friend_request(20), true),
+        (group_request(10, 20, "add"), true),
+        (friend_request(21), false),
+    ];
+    for (request, expected) in cases {
+        let handled = REQUEST_OF_USER
+            .process_request(bot_ctx.clone(), &request)
+            .await
+            .unwrap();
+        assert_eq!(handled, expected, "{:?}", request);
+    }
+}